@@ -0,0 +1,49 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
+use std::io::Error as IoError;
+
+use serde_json::Error as JsonError;
+
+#[derive(Debug)]
+pub enum AuditError {
+    IoError { message: String },
+    SerializationError { message: String },
+}
+
+impl AuditError {
+    pub fn io_error(error: IoError) -> AuditError {
+        AuditError::IoError {
+            message: format!("{}", error),
+        }
+    }
+
+    pub fn serialization_error(error: JsonError) -> AuditError {
+        AuditError::SerializationError {
+            message: format!("{}", error),
+        }
+    }
+}
+
+impl Display for AuditError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            AuditError::IoError { message } => write!(f, "IO error: {}", message),
+            AuditError::SerializationError { message } => {
+                write!(f, "Serialization error: {}", message)
+            }
+        }
+    }
+}
+
+impl Error for AuditError {
+    fn description(&self) -> &str {
+        match self {
+            AuditError::IoError { .. } => "IO error",
+            AuditError::SerializationError { .. } => "Serialization error",
+        }
+    }
+}
+
+pub type AuditResult<T> = Result<T, AuditError>;
@@ -0,0 +1,153 @@
+mod error;
+
+pub use self::error::AuditError;
+pub use self::error::AuditResult;
+
+use serde_json;
+
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::ErrorKind;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Append-only log with one JSON record per imported change.
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+/// Audit record of imported change. Record is kept in journal until commit is made, so node is
+/// set only when it is appended to log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    bookmark: String,
+    change: u32,
+    node: String,
+    author: String,
+    files: usize,
+    submitted_at: String,
+    imported_at: String,
+}
+
+#[derive(Deserialize)]
+struct RecordKey {
+    bookmark: String,
+    change: u32,
+}
+
+impl AuditLog {
+    pub fn new(path: &str) -> AuditLog {
+        AuditLog { path: path.into() }
+    }
+
+    /// Checks if log already contains record of change. Used on recovery, when record may be
+    /// appended before process was interrupted.
+    pub fn contains(&self, bookmark: &str, change: u32) -> AuditResult<bool> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(ref error) if error.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(error) => return Err(AuditError::io_error(error)),
+        };
+
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(AuditError::io_error)?;
+
+            // Last line may be incomplete if process was interrupted while appending.
+            if let Ok(key) = serde_json::from_str::<RecordKey>(&line) {
+                if key.bookmark == bookmark && key.change == change {
+                    return Ok(true);
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    pub fn append(&self, record: &AuditRecord) -> AuditResult<()> {
+        debug!("Appending audit record {:?}.", record);
+        let mut line = serde_json::to_string(record).map_err(AuditError::serialization_error)?;
+        line.push('\n');
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(AuditError::io_error)?;
+
+        file.write_all(line.as_bytes())
+            .map_err(AuditError::io_error)?;
+        file.sync_data().map_err(AuditError::io_error)?;
+
+        Ok(())
+    }
+}
+
+impl AuditRecord {
+    pub fn new(
+        bookmark: &str,
+        change: u32,
+        author: &str,
+        files: usize,
+        submitted_at: &str,
+        imported_at: &str,
+    ) -> AuditRecord {
+        AuditRecord {
+            bookmark: bookmark.into(),
+            change,
+            node: String::new(),
+            author: author.into(),
+            files,
+            submitted_at: submitted_at.into(),
+            imported_at: imported_at.into(),
+        }
+    }
+
+    pub fn bookmark(&self) -> &str {
+        &self.bookmark
+    }
+
+    pub fn change(&self) -> u32 {
+        self.change
+    }
+
+    pub fn set_node(&mut self, node: &str) {
+        self.node = node.into();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use testing::temp_path;
+
+    #[test]
+    fn finds_appended_records() {
+        let path = temp_path("records.audit");
+        let _ = fs::remove_file(&path);
+        let audit_log = AuditLog::new(&path.display().to_string());
+        let mut record = AuditRecord::new("main", 42, "user", 3, "", "");
+
+        record.set_node("0123");
+        assert!(!audit_log.contains("main", 42).unwrap());
+
+        audit_log.append(&record).unwrap();
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"bookmark\":\"main\",\"cha")
+            .unwrap();
+
+        assert!(audit_log.contains("main", 42).unwrap());
+        assert!(!audit_log.contains("main", 43).unwrap());
+        assert!(!audit_log.contains("other", 42).unwrap());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
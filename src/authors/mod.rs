@@ -0,0 +1,131 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use std::time::Instant;
+
+use config::AuthorsConfig;
+use perforce::PerforceClient;
+use perforce::PerforceResult;
+
+const MISS_REFRESH_INTERVAL: u64 = 60;
+
+/// Resolves Perforce user names to Mercurial authors. Authors file entries take precedence
+/// over Perforce user specs. Perforce users are cached for configured time and reloaded
+/// when unknown user is found.
+#[derive(Debug)]
+pub struct AuthorResolver<'a> {
+    config: Option<&'a AuthorsConfig>,
+    cache: RefCell<Option<UserCache>>,
+}
+
+#[derive(Debug)]
+struct UserCache {
+    authors: BTreeMap<String, String>,
+    loaded_at: Instant,
+}
+
+impl<'a> AuthorResolver<'a> {
+    pub fn new(config: Option<&'a AuthorsConfig>) -> AuthorResolver<'a> {
+        AuthorResolver {
+            config,
+            cache: RefCell::new(None),
+        }
+    }
+
+    pub fn resolve(&self, user: &str, p4_client: &mut PerforceClient) -> PerforceResult<String> {
+        let config = match self.config {
+            Some(config) => config,
+            None => return Ok(user.into()),
+        };
+
+        if let Some(author) = config.entries().get(user) {
+            return Ok(author.clone());
+        }
+
+        let cache_time = Duration::from_secs(config.cache_time());
+        let refresh = match *self.cache.borrow() {
+            None => true,
+            Some(ref cache) if cache.loaded_at.elapsed() >= cache_time => true,
+            Some(ref cache) => {
+                !cache.authors.contains_key(user)
+                    && cache.loaded_at.elapsed() >= Duration::from_secs(MISS_REFRESH_INTERVAL)
+            }
+        };
+
+        if refresh {
+            self.load(p4_client)?;
+        }
+
+        match *self.cache.borrow() {
+            Some(ref cache) if cache.authors.contains_key(user) => Ok(cache.authors[user].clone()),
+            _ => {
+                debug!("Unknown author {}, using fallback.", user);
+
+                Ok(config.fallback().replace("{user}", user))
+            }
+        }
+    }
+
+    fn load(&self, p4_client: &mut PerforceClient) -> PerforceResult<()> {
+        info!("Loading Perforce users.");
+        let mut authors = BTreeMap::new();
+
+        for user in p4_client.users()? {
+            let author = match (user.full_name(), user.email()) {
+                ("", "") => continue,
+                ("", email) => format!("{} <{}>", user.user(), email),
+                (full_name, "") => full_name.to_string(),
+                (full_name, email) => format!("{} <{}>", full_name, email),
+            };
+
+            authors.insert(user.user().to_string(), author);
+        }
+
+        *self.cache.borrow_mut() = Some(UserCache {
+            authors,
+            loaded_at: Instant::now(),
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use testing::config;
+    use testing::temp_path;
+
+    #[test]
+    fn prefers_authors_file() {
+        let path = temp_path("authors.yaml");
+        fs::write(&path, "alice: Alice Smith <alice@example.com>\n").unwrap();
+
+        let config = config(&format!(
+            "authors:\n  file: {}\nmappings: []\n",
+            path.display()
+        ));
+        let mut p4_client = PerforceClient::new("p4", "/work", "client", "", "user", "", "");
+
+        assert_eq!(
+            AuthorResolver::new(config.authors())
+                .resolve("alice", &mut p4_client)
+                .unwrap(),
+            "Alice Smith <alice@example.com>"
+        );
+        assert_eq!(
+            AuthorResolver::new(None)
+                .resolve("alice", &mut p4_client)
+                .unwrap(),
+            "alice"
+        );
+        assert!(AuthorResolver::new(config.authors())
+            .resolve("bob", &mut p4_client)
+            .is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -4,12 +4,18 @@ use std::fmt::Formatter;
 use std::fmt::Result as FmtResult;
 use std::io::Error as IoError;
 
+use regex::Error as RegexError;
 use serde_yaml::Error as YamlError;
 
 #[derive(Debug)]
 pub enum ConfigError {
     IoError { message: String },
     DeserializationError { message: String },
+    RegexError { message: String },
+    InvalidMapping { bookmark: String },
+    InvalidWorkspaceOption { option: String },
+    InvalidLease { duration: u64, minimum: u64 },
+    InvalidWebhookUrl { url: String },
 }
 
 impl ConfigError {
@@ -24,6 +30,32 @@ impl ConfigError {
             message: error.description().into(),
         }
     }
+
+    pub fn invalid_mapping(bookmark: &str) -> ConfigError {
+        ConfigError::InvalidMapping {
+            bookmark: bookmark.into(),
+        }
+    }
+
+    pub fn invalid_workspace_option(option: &str) -> ConfigError {
+        ConfigError::InvalidWorkspaceOption {
+            option: option.into(),
+        }
+    }
+
+    pub fn invalid_lease(duration: u64, minimum: u64) -> ConfigError {
+        ConfigError::InvalidLease { duration, minimum }
+    }
+
+    pub fn invalid_webhook_url(url: &str) -> ConfigError {
+        ConfigError::InvalidWebhookUrl { url: url.into() }
+    }
+
+    pub fn regex_error(error: RegexError) -> ConfigError {
+        ConfigError::RegexError {
+            message: error.to_string(),
+        }
+    }
 }
 
 impl Display for ConfigError {
@@ -33,6 +65,21 @@ impl Display for ConfigError {
             ConfigError::DeserializationError { message } => {
                 write!(f, "Deserialization error: {}", message)
             }
+            ConfigError::RegexError { message } => write!(f, "Regex error: {}", message),
+            ConfigError::InvalidMapping { bookmark } => {
+                write!(f, "Mapping {} has no depot paths", bookmark)
+            }
+            ConfigError::InvalidWorkspaceOption { option } => {
+                write!(f, "Unknown workspace option {}", option)
+            }
+            ConfigError::InvalidLease { duration, minimum } => write!(
+                f,
+                "Lease duration {} must be greater than update interval plus cycle time {}",
+                duration, minimum
+            ),
+            ConfigError::InvalidWebhookUrl { url } => {
+                write!(f, "Webhook URL {} must start with http://", url)
+            }
         }
     }
 }
@@ -42,6 +89,11 @@ impl Error for ConfigError {
         match self {
             ConfigError::IoError { .. } => "IO error",
             ConfigError::DeserializationError { .. } => "Deserialization error",
+            ConfigError::RegexError { .. } => "Regex error",
+            ConfigError::InvalidMapping { .. } => "Invalid mapping",
+            ConfigError::InvalidWorkspaceOption { .. } => "Invalid workspace option",
+            ConfigError::InvalidLease { .. } => "Invalid lease",
+            ConfigError::InvalidWebhookUrl { .. } => "Invalid webhook URL",
         }
     }
 }
@@ -3,15 +3,36 @@ mod error;
 pub use self::error::ConfigError;
 pub use self::error::ConfigResult;
 
+use std::collections::BTreeMap;
 use std::fs::File;
+use std::io::Read;
 use std::path::Path;
 
+use regex::Regex;
 use serde_yaml;
 
+use perforce::CLIENT_OPTIONS;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     update_interval: u64,
     batch_size: usize,
+    #[serde(default)]
+    lock_directory: Option<String>,
+    #[serde(default)]
+    lease: Option<LeaseConfig>,
+    #[serde(default)]
+    http: Option<HttpConfig>,
+    #[serde(default)]
+    socket: Option<String>,
+    #[serde(default)]
+    log_format: LogFormat,
+    #[serde(default)]
+    audit_log: Option<String>,
+    #[serde(default)]
+    notifications: Option<NotificationsConfig>,
+    #[serde(default)]
+    authors: Option<AuthorsConfig>,
     perforce: PerforceConfig,
     mercurial: MercurialConfig,
     mappings: Vec<MappingConfig>,
@@ -26,18 +47,188 @@ pub struct PerforceConfig {
     user: String,
     password: String,
     ignore: String,
+    #[serde(default)]
+    charset: Option<String>,
+    #[serde(default)]
+    workspace: Option<WorkspaceConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WorkspaceConfig {
+    #[serde(default = "default_workspace_options")]
+    options: Vec<String>,
+    #[serde(default = "default_line_end")]
+    line_end: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LeaseConfig {
+    #[serde(alias = "counter")]
+    key: String,
+    duration: u64,
+    /// Expected maximal time between lease renewals, only used to validate duration.
+    #[serde(default = "default_cycle_time")]
+    cycle_time: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HttpConfig {
+    address: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotificationsConfig {
+    #[serde(default)]
+    lag_threshold: Option<u64>,
+    #[serde(default = "default_min_interval")]
+    min_interval: u64,
+    #[serde(default)]
+    smtp: Option<SmtpConfig>,
+    #[serde(default)]
+    webhook: Option<WebhookConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SmtpConfig {
+    server: String,
+    sender: String,
+    recipients: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthorsConfig {
+    #[serde(default)]
+    file: Option<String>,
+    #[serde(default = "default_author_fallback")]
+    fallback: String,
+    #[serde(default = "default_author_cache_time")]
+    cache_time: u64,
+    #[serde(skip)]
+    entries: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MercurialConfig {
     command: String,
+    #[serde(default)]
+    message_template: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MappingConfig {
-    depot_directory: String,
+    #[serde(default)]
+    depot_directory: Option<String>,
+    #[serde(default)]
+    sources: Vec<SourceConfig>,
     bookmark: String,
     local_directory: String,
+    #[serde(default)]
+    start_change: Option<u32>,
+    #[serde(default)]
+    mode: ImportMode,
+    #[serde(default)]
+    filters: Option<FiltersConfig>,
+    #[serde(default)]
+    grouping: Option<GroupingConfig>,
+    #[serde(default)]
+    collapse_keywords: bool,
+    #[serde(default)]
+    transforms: Vec<TransformConfig>,
+    #[serde(default)]
+    ignored_files: IgnoredFiles,
+    #[serde(default)]
+    verify: bool,
+}
+
+/// How pending changes are imported: one commit per change or a single commit of head state.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    #[default]
+    Changes,
+    Snapshot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IgnoredFiles {
+    #[default]
+    Report,
+    Add,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransformConfig {
+    #[serde(default)]
+    file_types: Vec<String>,
+    #[serde(default)]
+    paths: Vec<String>,
+    #[serde(default)]
+    utf8: bool,
+    #[serde(default)]
+    line_endings: LineEndings,
+    #[serde(skip)]
+    path_regexes: Vec<Regex>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEndings {
+    #[default]
+    Keep,
+    Lf,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SourceConfig {
+    depot_path: String,
+    #[serde(default)]
+    target_subdir: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GroupingConfig {
+    #[serde(default = "default_group_window")]
+    window: i64,
+    #[serde(default)]
+    by_user: bool,
+    #[serde(default)]
+    descriptions: Vec<String>,
+    #[serde(skip)]
+    description_regexes: Vec<Regex>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FiltersConfig {
+    #[serde(default)]
+    users: Vec<String>,
+    #[serde(default)]
+    descriptions: Vec<String>,
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    #[serde(skip)]
+    user_regexes: Vec<Regex>,
+    #[serde(skip)]
+    description_regexes: Vec<Regex>,
+    #[serde(skip)]
+    include_regexes: Vec<Regex>,
+    #[serde(skip)]
+    exclude_regexes: Vec<Regex>,
 }
 
 impl Config {
@@ -46,7 +237,82 @@ impl Config {
         P: AsRef<Path>,
     {
         let file = File::open(path).map_err(ConfigError::io_error)?;
-        let config = serde_yaml::from_reader(file).map_err(ConfigError::deserialization_error)?;
+
+        Config::from_reader(file)
+    }
+
+    pub fn from_reader<R>(reader: R) -> ConfigResult<Config>
+    where
+        R: Read,
+    {
+        let mut config: Config =
+            serde_yaml::from_reader(reader).map_err(ConfigError::deserialization_error)?;
+
+        if let Some(ref mut authors) = config.authors {
+            if let Some(ref file) = authors.file {
+                let file = File::open(file).map_err(ConfigError::io_error)?;
+
+                authors.entries =
+                    serde_yaml::from_reader(file).map_err(ConfigError::deserialization_error)?;
+            }
+        }
+
+        if let Some(ref lease) = config.lease {
+            let minimum = config.update_interval + lease.cycle_time;
+
+            if lease.duration <= minimum {
+                return Err(ConfigError::invalid_lease(lease.duration, minimum));
+            }
+        }
+
+        if let Some(ref workspace) = config.perforce.workspace {
+            for option in &workspace.options {
+                if !CLIENT_OPTIONS.iter().any(|&(name, _)| name == option) {
+                    return Err(ConfigError::invalid_workspace_option(option));
+                }
+            }
+        }
+
+        if let Some(webhook) = config
+            .notifications
+            .as_ref()
+            .and_then(|notifications| notifications.webhook.as_ref())
+        {
+            if !webhook.url.starts_with("http://") {
+                return Err(ConfigError::invalid_webhook_url(&webhook.url));
+            }
+        }
+
+        for mapping in &mut config.mappings {
+            if let Some(depot_directory) = mapping.depot_directory.take() {
+                mapping.sources.insert(
+                    0,
+                    SourceConfig {
+                        depot_path: depot_directory,
+                        target_subdir: String::new(),
+                    },
+                );
+            }
+
+            if mapping.sources.is_empty() {
+                return Err(ConfigError::invalid_mapping(&mapping.bookmark));
+            }
+
+            if let Some(ref mut filters) = mapping.filters {
+                filters.user_regexes = compile(&filters.users)?;
+                filters.description_regexes = compile(&filters.descriptions)?;
+                filters.include_regexes = compile_globs(&filters.include)?;
+                filters.exclude_regexes = compile_globs(&filters.exclude)?;
+            }
+
+            if let Some(ref mut grouping) = mapping.grouping {
+                grouping.description_regexes = compile(&grouping.descriptions)?;
+            }
+
+            for transform in &mut mapping.transforms {
+                transform.path_regexes = compile_globs(&transform.paths)?;
+            }
+        }
 
         Ok(config)
     }
@@ -61,6 +327,46 @@ impl Config {
         self.batch_size
     }
 
+    #[inline]
+    pub fn lock_directory(&self) -> Option<&String> {
+        self.lock_directory.as_ref()
+    }
+
+    #[inline]
+    pub fn lease(&self) -> Option<&LeaseConfig> {
+        self.lease.as_ref()
+    }
+
+    #[inline]
+    pub fn http(&self) -> Option<&HttpConfig> {
+        self.http.as_ref()
+    }
+
+    #[inline]
+    pub fn socket(&self) -> Option<&String> {
+        self.socket.as_ref()
+    }
+
+    #[inline]
+    pub fn log_format(&self) -> LogFormat {
+        self.log_format
+    }
+
+    #[inline]
+    pub fn audit_log(&self) -> Option<&String> {
+        self.audit_log.as_ref()
+    }
+
+    #[inline]
+    pub fn notifications(&self) -> Option<&NotificationsConfig> {
+        self.notifications.as_ref()
+    }
+
+    #[inline]
+    pub fn authors(&self) -> Option<&AuthorsConfig> {
+        self.authors.as_ref()
+    }
+
     #[inline]
     pub fn perforce(&self) -> &PerforceConfig {
         &self.perforce
@@ -105,17 +411,126 @@ impl PerforceConfig {
     pub fn ignore(&self) -> &String {
         &self.ignore
     }
+
+    pub fn charset(&self) -> Option<&String> {
+        self.charset.as_ref()
+    }
+
+    /// Managed workspace settings. When set workspace spec is created or updated to match
+    /// mappings.
+    pub fn workspace(&self) -> Option<&WorkspaceConfig> {
+        self.workspace.as_ref()
+    }
+}
+
+impl WorkspaceConfig {
+    pub fn options(&self) -> &[String] {
+        self.options.as_ref()
+    }
+
+    pub fn line_end(&self) -> &String {
+        &self.line_end
+    }
+}
+
+impl LeaseConfig {
+    /// Perforce key to store lease in.
+    pub fn key(&self) -> &String {
+        &self.key
+    }
+
+    pub fn duration(&self) -> u64 {
+        self.duration
+    }
+}
+
+impl HttpConfig {
+    pub fn address(&self) -> &String {
+        &self.address
+    }
+}
+
+impl NotificationsConfig {
+    pub fn lag_threshold(&self) -> Option<u64> {
+        self.lag_threshold
+    }
+
+    pub fn min_interval(&self) -> u64 {
+        self.min_interval
+    }
+
+    pub fn smtp(&self) -> Option<&SmtpConfig> {
+        self.smtp.as_ref()
+    }
+
+    pub fn webhook(&self) -> Option<&WebhookConfig> {
+        self.webhook.as_ref()
+    }
+}
+
+impl SmtpConfig {
+    pub fn server(&self) -> &String {
+        &self.server
+    }
+
+    pub fn sender(&self) -> &String {
+        &self.sender
+    }
+
+    pub fn recipients(&self) -> &[String] {
+        self.recipients.as_ref()
+    }
+}
+
+impl WebhookConfig {
+    pub fn url(&self) -> &String {
+        &self.url
+    }
+}
+
+impl AuthorsConfig {
+    pub fn fallback(&self) -> &String {
+        &self.fallback
+    }
+
+    pub fn cache_time(&self) -> u64 {
+        self.cache_time
+    }
+
+    /// Authors from authors file, Perforce user name to Mercurial author.
+    pub fn entries(&self) -> &BTreeMap<String, String> {
+        &self.entries
+    }
 }
 
 impl MercurialConfig {
     pub fn command(&self) -> &String {
         &self.command
     }
+
+    pub fn message_template(&self) -> Option<&String> {
+        self.message_template.as_ref()
+    }
 }
 
 impl MappingConfig {
-    pub fn depot_directory(&self) -> &String {
-        &self.depot_directory
+    /// Depot paths with target subdirectories, `depot_directory` is the first source with
+    /// empty target subdirectory.
+    pub fn sources(&self) -> &[SourceConfig] {
+        self.sources.as_ref()
+    }
+
+    /// Depot paths of all sources, every path ends with `/`.
+    pub fn depot_paths(&self) -> Vec<String> {
+        self.sources
+            .iter()
+            .map(|source| format!("{}/", source.depot_path.trim_end_matches('/')))
+            .collect()
+    }
+
+    /// Comma separated depot paths for logs, status and commit messages.
+    pub fn depot_path(&self) -> String {
+        self.depot_paths().join(", ")
     }
 
     pub fn bookmark(&self) -> &String {
@@ -125,4 +540,302 @@ impl MappingConfig {
     pub fn local_directory(&self) -> &String {
         &self.local_directory
     }
+
+    /// Change of initial snapshot for repository without imported changes.
+    pub fn start_change(&self) -> Option<u32> {
+        self.start_change
+    }
+
+    pub fn mode(&self) -> ImportMode {
+        self.mode
+    }
+
+    pub fn filters(&self) -> Option<&FiltersConfig> {
+        self.filters.as_ref()
+    }
+
+    pub fn grouping(&self) -> Option<&GroupingConfig> {
+        self.grouping.as_ref()
+    }
+
+    /// Collapse RCS keywords of `+k` files before commit.
+    pub fn collapse_keywords(&self) -> bool {
+        self.collapse_keywords
+    }
+
+    /// Verify every commit against Perforce digests.
+    pub fn verify(&self) -> bool {
+        self.verify
+    }
+
+    /// Policy for Perforce files ignored by `.hgignore`.
+    pub fn ignored_files(&self) -> IgnoredFiles {
+        self.ignored_files
+    }
+
+    /// Returns first content transform matching file base type and path relative to local
+    /// directory.
+    pub fn transform(&self, file_type: &str, path: &str) -> Option<&TransformConfig> {
+        self.transforms
+            .iter()
+            .find(|transform| transform.matches(file_type, path))
+    }
+}
+
+impl SourceConfig {
+    pub fn depot_path(&self) -> &String {
+        &self.depot_path
+    }
+
+    pub fn target_subdir(&self) -> &String {
+        &self.target_subdir
+    }
+}
+
+impl TransformConfig {
+    pub fn utf8(&self) -> bool {
+        self.utf8
+    }
+
+    pub fn line_endings(&self) -> LineEndings {
+        self.line_endings
+    }
+
+    fn matches(&self, file_type: &str, path: &str) -> bool {
+        (self.file_types.is_empty() || self.file_types.iter().any(|name| name == file_type))
+            && (self.path_regexes.is_empty()
+                || self.path_regexes.iter().any(|regex| regex.is_match(path)))
+    }
+}
+
+impl GroupingConfig {
+    /// Maximal time in seconds between consecutive changes of one group.
+    pub fn window(&self) -> i64 {
+        self.window
+    }
+
+    /// Group consecutive changes of the same user.
+    pub fn by_user(&self) -> bool {
+        self.by_user
+    }
+
+    /// Checks whether change description matches any of grouping patterns.
+    pub fn matches_description(&self, description: &str) -> bool {
+        self.description_regexes
+            .iter()
+            .any(|regex| regex.is_match(description))
+    }
+}
+
+impl FiltersConfig {
+    /// Mercurial glob patterns of files to import, all files by default.
+    pub fn include(&self) -> &[String] {
+        self.include.as_ref()
+    }
+
+    /// Mercurial glob patterns of files to leave out of commits.
+    pub fn exclude(&self) -> &[String] {
+        self.exclude.as_ref()
+    }
+
+    /// Checks whether file with path relative to local directory is imported by include and
+    /// exclude rules.
+    pub fn is_included(&self, path: &str) -> bool {
+        (self.include_regexes.is_empty()
+            || self
+                .include_regexes
+                .iter()
+                .any(|regex| regex.is_match(path)))
+            && !self
+                .exclude_regexes
+                .iter()
+                .any(|regex| regex.is_match(path))
+    }
+
+    /// Checks whether there are user or description rules, so some changes may be skipped.
+    pub fn skips_changes(&self) -> bool {
+        !self.user_regexes.is_empty() || !self.description_regexes.is_empty()
+    }
+
+    /// Checks whether change should be skipped by user or description rules.
+    pub fn skips(&self, user: &str, description: &str) -> bool {
+        self.user_regexes.iter().any(|regex| regex.is_match(user))
+            || self
+                .description_regexes
+                .iter()
+                .any(|regex| regex.is_match(description))
+    }
+}
+
+fn compile(patterns: &[String]) -> ConfigResult<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|pattern| Regex::new(pattern).map_err(ConfigError::regex_error))
+        .collect()
+}
+
+fn compile_globs(patterns: &[String]) -> ConfigResult<Vec<Regex>> {
+    patterns.iter().map(|pattern| glob_regex(pattern)).collect()
+}
+
+/// Converts Mercurial glob pattern to regex: `*` and `?` do not match `/`, `**` matches any
+/// number of directories, pattern matching directory matches all files inside.
+fn glob_regex(pattern: &str) -> ConfigResult<Regex> {
+    let mut result = String::from("^");
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    result.push_str("(?:.*/)?");
+                } else {
+                    result.push_str(".*");
+                }
+            }
+            '*' => result.push_str("[^/]*"),
+            '?' => result.push_str("[^/]"),
+            _ => result.push_str(&regex::escape(&ch.to_string())),
+        }
+    }
+
+    result.push_str("(?:/.*)?$");
+
+    Regex::new(&result).map_err(ConfigError::regex_error)
+}
+
+fn default_cycle_time() -> u64 {
+    600
+}
+
+fn default_workspace_options() -> Vec<String> {
+    vec!["clobber".into(), "rmdir".into()]
+}
+
+fn default_line_end() -> String {
+    "local".into()
+}
+
+fn default_group_window() -> i64 {
+    60
+}
+
+fn default_min_interval() -> u64 {
+    3600
+}
+
+fn default_author_fallback() -> String {
+    "{user}".into()
+}
+
+fn default_author_cache_time() -> u64 {
+    3600
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use testing::config_text;
+
+    fn config(text: &str) -> ConfigResult<Config> {
+        Config::from_reader(config_text("hg", text).as_bytes())
+    }
+
+    #[test]
+    fn lease_must_outlive_cycle() {
+        let valid = config(
+            "
+update_interval: 60
+batch_size: 10
+lease:
+  key: perforce-sync-lease
+  duration: 900
+  cycle_time: 300
+mappings: []
+",
+        )
+        .unwrap();
+
+        assert_eq!(valid.lease().unwrap().key(), "perforce-sync-lease");
+
+        let invalid = config(
+            "
+update_interval: 60
+batch_size: 10
+lease:
+  counter: perforce-sync-lease
+  duration: 600
+mappings: []
+",
+        );
+
+        match invalid {
+            Err(ConfigError::InvalidLease { duration, minimum }) => {
+                assert_eq!((duration, minimum), (600, 660));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn webhook_must_use_http() {
+        let invalid = config(
+            "
+update_interval: 60
+batch_size: 10
+notifications:
+  webhook:
+    url: https://hooks.example.com/sync
+mappings: []
+",
+        );
+
+        match invalid {
+            Err(ConfigError::InvalidWebhookUrl { url }) => {
+                assert_eq!(url, "https://hooks.example.com/sync");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn filters_match_globs() {
+        let config = config(
+            "
+update_interval: 60
+batch_size: 10
+mappings:
+  - depot_directory: //depot/project
+    bookmark: project
+    local_directory: /repo
+    filters:
+      users: [\"^build$\"]
+      descriptions: [\"^\\\\[auto\\\\]\"]
+      include: [\"src\", \"*.md\"]
+      exclude: [\"**/*.generated.cs\", \"src/gen?/**\"]
+",
+        )
+        .unwrap();
+        let filters = config.mappings()[0].filters().unwrap();
+
+        assert!(filters.is_included("README.md"));
+        assert!(!filters.is_included("docs/README.md"));
+        assert!(filters.is_included("src/main.cs"));
+        assert!(filters.is_included("src/a/b/main.cs"));
+        assert!(!filters.is_included("src/a/b/main.generated.cs"));
+        assert!(!filters.is_included("main.generated.cs"));
+        assert!(!filters.is_included("src/gen1/main.cs"));
+        assert!(filters.is_included("src/gen12/main.cs"));
+        assert!(!filters.is_included("lib/main.cs"));
+
+        assert!(filters.skips_changes());
+        assert!(filters.skips("build", "Nightly"));
+        assert!(!filters.skips("builder", "Fix"));
+        assert!(filters.skips("user", "[auto] Bump version"));
+        assert!(!filters.skips("user", "Fix [auto] build"));
+    }
 }
@@ -0,0 +1,172 @@
+use std::collections::BTreeSet;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Runtime commands received from control socket and honoured by worker between changes.
+#[derive(Debug, Default)]
+pub struct Control {
+    state: Mutex<ControlState>,
+    condvar: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct ControlState {
+    paused: BTreeSet<String>,
+    force_sync: bool,
+    drain: bool,
+    reload: bool,
+}
+
+/// Reason of worker loop exit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ControlExit {
+    Drain,
+    Reload,
+}
+
+impl Control {
+    pub fn new() -> Control {
+        Control::default()
+    }
+
+    pub fn pause(&self, bookmark: &str) {
+        info!("Pausing mapping, bookmark = {}", bookmark);
+        self.lock().paused.insert(bookmark.into());
+    }
+
+    pub fn resume(&self, bookmark: &str) {
+        info!("Resuming mapping, bookmark = {}", bookmark);
+        self.lock().paused.remove(bookmark);
+    }
+
+    pub fn force_sync(&self) {
+        info!("Forcing synchronization");
+        self.lock().force_sync = true;
+        self.condvar.notify_all();
+    }
+
+    pub fn drain(&self) {
+        info!("Draining");
+        self.lock().drain = true;
+        self.condvar.notify_all();
+    }
+
+    pub fn reload(&self) {
+        info!("Reloading configuration");
+        self.lock().reload = true;
+        self.condvar.notify_all();
+    }
+
+    pub fn is_paused(&self, bookmark: &str) -> bool {
+        self.lock().paused.contains(bookmark)
+    }
+
+    /// Returns `true` if mapping processing should stop after current change.
+    pub fn should_interrupt(&self, bookmark: &str) -> bool {
+        let state = self.lock();
+
+        state.drain || state.reload || state.paused.contains(bookmark)
+    }
+
+    /// Returns exit reason if worker loop should stop. Reload request is consumed.
+    pub fn take_exit(&self) -> Option<ControlExit> {
+        let mut state = self.lock();
+
+        if state.drain {
+            Some(ControlExit::Drain)
+        } else if state.reload {
+            state.reload = false;
+
+            Some(ControlExit::Reload)
+        } else {
+            None
+        }
+    }
+
+    /// Sleeps for given duration or until force sync, drain or reload requested.
+    pub fn wait(&self, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        let mut state = self.lock();
+
+        loop {
+            if state.force_sync || state.drain || state.reload {
+                state.force_sync = false;
+
+                break;
+            }
+
+            let now = Instant::now();
+
+            if now >= deadline {
+                break;
+            }
+
+            state = match self.condvar.wait_timeout(state, deadline - now) {
+                Ok((state, _)) => state,
+                Err(poisoned) => poisoned.into_inner().0,
+            };
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ControlState> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interrupts_paused_mappings() {
+        let control = Control::new();
+
+        control.pause("main");
+
+        assert!(control.is_paused("main"));
+        assert!(control.should_interrupt("main"));
+        assert!(!control.should_interrupt("release"));
+
+        control.resume("main");
+
+        assert!(!control.should_interrupt("main"));
+        assert_eq!(control.take_exit(), None);
+    }
+
+    #[test]
+    fn consumes_reload_and_keeps_drain() {
+        let control = Control::new();
+
+        control.reload();
+
+        assert!(control.should_interrupt("main"));
+        assert_eq!(control.take_exit(), Some(ControlExit::Reload));
+        assert_eq!(control.take_exit(), None);
+
+        control.drain();
+
+        assert_eq!(control.take_exit(), Some(ControlExit::Drain));
+        assert_eq!(control.take_exit(), Some(ControlExit::Drain));
+    }
+
+    #[test]
+    fn force_sync_ends_wait() {
+        let control = Control::new();
+        let started = Instant::now();
+
+        control.force_sync();
+        control.wait(Duration::from_secs(60));
+
+        assert!(started.elapsed() < Duration::from_secs(60));
+
+        control.wait(Duration::from_millis(10));
+
+        assert!(started.elapsed() >= Duration::from_millis(10));
+    }
+}
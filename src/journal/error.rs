@@ -0,0 +1,61 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
+use std::io::Error as IoError;
+
+use serde_yaml::Error as YamlError;
+
+#[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
+pub enum JournalError {
+    IoError { message: String },
+    SerializationError { message: String },
+    DeserializationError { message: String },
+}
+
+impl JournalError {
+    pub fn io_error(error: IoError) -> JournalError {
+        JournalError::IoError {
+            message: format!("{}", error),
+        }
+    }
+
+    pub fn serialization_error(error: YamlError) -> JournalError {
+        JournalError::SerializationError {
+            message: format!("{}", error),
+        }
+    }
+
+    pub fn deserialization_error(error: YamlError) -> JournalError {
+        JournalError::DeserializationError {
+            message: format!("{}", error),
+        }
+    }
+}
+
+impl Display for JournalError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            JournalError::IoError { message } => write!(f, "IO error: {}", message),
+            JournalError::SerializationError { message } => {
+                write!(f, "Serialization error: {}", message)
+            }
+            JournalError::DeserializationError { message } => {
+                write!(f, "Deserialization error: {}", message)
+            }
+        }
+    }
+}
+
+impl Error for JournalError {
+    fn description(&self) -> &str {
+        match self {
+            JournalError::IoError { .. } => "IO error",
+            JournalError::SerializationError { .. } => "Serialization error",
+            JournalError::DeserializationError { .. } => "Deserialization error",
+        }
+    }
+}
+
+pub type JournalResult<T> = Result<T, JournalError>;
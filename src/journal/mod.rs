@@ -0,0 +1,266 @@
+mod error;
+
+pub use self::error::JournalError;
+pub use self::error::JournalResult;
+
+use std::fs;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::io::Write;
+use std::path::PathBuf;
+
+use serde_yaml;
+
+use audit::AuditRecord;
+
+/// Step of change import. Every step is written to journal before it is performed.
+/// `VerifyFailed` is written after commit differs from Perforce, it blocks mapping until
+/// operator removes or pushes the commit. `Backfill` is written while workspace and working
+/// copy are used by backfill of history.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum JournalStep {
+    Sync,
+    Stage,
+    Commit,
+    VerifyFailed,
+    Push,
+    Backfill,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    step: JournalStep,
+    change: u32,
+    previous: Option<u32>,
+    pending_push: bool,
+    #[serde(default)]
+    audit: Vec<AuditRecord>,
+}
+
+/// Last skipped change and the last commit of bookmark at the moment it was skipped.
+#[derive(Debug, Serialize, Deserialize)]
+struct SkippedEntry {
+    change: u32,
+    previous: Option<u32>,
+}
+
+/// Per-mapping journal stored in `.hg` directory of Mercurial repository. Besides interrupted
+/// step journal keeps last skipped change, so skipped changes are not processed again.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    skipped_path: PathBuf,
+}
+
+impl Journal {
+    pub fn new(local_directory: &str, bookmark: &str) -> Journal {
+        let name: String = bookmark
+            .chars()
+            .map(|ch| if ch.is_ascii_alphanumeric() { ch } else { '_' })
+            .collect();
+        let mut path = PathBuf::new();
+        path.push(local_directory);
+        path.push(".hg");
+        path.push(format!("perforce-sync-{}.journal", name));
+        let skipped_path = path.with_file_name(format!("perforce-sync-{}.skipped", name));
+
+        Journal { path, skipped_path }
+    }
+
+    pub fn read(&self) -> JournalResult<Option<JournalEntry>> {
+        debug!("Reading journal {}.", self.path.display());
+        match File::open(&self.path) {
+            Ok(file) => {
+                let entry =
+                    serde_yaml::from_reader(file).map_err(JournalError::deserialization_error)?;
+
+                Ok(Some(entry))
+            }
+            Err(ref error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(JournalError::io_error(error)),
+        }
+    }
+
+    pub fn write(&self, entry: &JournalEntry) -> JournalResult<()> {
+        debug!(
+            "Writing journal {}, entry = {:?}.",
+            self.path.display(),
+            entry
+        );
+        let content = serde_yaml::to_string(entry).map_err(JournalError::serialization_error)?;
+        let temp_path = self.path.with_extension("journal.tmp");
+
+        {
+            let mut file = File::create(&temp_path).map_err(JournalError::io_error)?;
+
+            file.write_all(content.as_bytes())
+                .map_err(JournalError::io_error)?;
+            file.sync_all().map_err(JournalError::io_error)?;
+        }
+
+        fs::rename(&temp_path, &self.path).map_err(JournalError::io_error)?;
+
+        Ok(())
+    }
+
+    pub fn clear(&self) -> JournalResult<()> {
+        debug!("Clearing journal {}.", self.path.display());
+        match fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(ref error) if error.kind() == ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(JournalError::io_error(error)),
+        }
+    }
+
+    /// Returns last skipped change if it was skipped right after `previous` commit. Change
+    /// skipped after another commit, for example after bookmark was moved back, is ignored.
+    pub fn last_skipped(&self, previous: Option<u32>) -> JournalResult<Option<u32>> {
+        match File::open(&self.skipped_path) {
+            Ok(file) => {
+                let entry: SkippedEntry =
+                    serde_yaml::from_reader(file).map_err(JournalError::deserialization_error)?;
+
+                if entry.previous == previous {
+                    Ok(Some(entry.change))
+                } else {
+                    warn!(
+                        "Ignoring skipped change {}, it was skipped after {:?}",
+                        entry.change, entry.previous
+                    );
+
+                    Ok(None)
+                }
+            }
+            Err(ref error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(JournalError::io_error(error)),
+        }
+    }
+
+    /// Records change skipped after `previous` commit.
+    pub fn set_last_skipped(&self, change: u32, previous: Option<u32>) -> JournalResult<()> {
+        debug!(
+            "Writing skipped change {}, change = {}.",
+            self.skipped_path.display(),
+            change
+        );
+        let entry = SkippedEntry { change, previous };
+        let content = serde_yaml::to_string(&entry).map_err(JournalError::serialization_error)?;
+        let temp_path = self.skipped_path.with_extension("skipped.tmp");
+
+        {
+            let mut file = File::create(&temp_path).map_err(JournalError::io_error)?;
+
+            file.write_all(content.as_bytes())
+                .map_err(JournalError::io_error)?;
+            file.sync_all().map_err(JournalError::io_error)?;
+        }
+
+        fs::rename(&temp_path, &self.skipped_path).map_err(JournalError::io_error)?;
+
+        Ok(())
+    }
+}
+
+impl JournalEntry {
+    pub fn new(
+        step: JournalStep,
+        change: u32,
+        previous: Option<u32>,
+        pending_push: bool,
+    ) -> JournalEntry {
+        JournalEntry {
+            step,
+            change,
+            previous,
+            pending_push,
+            audit: Vec::new(),
+        }
+    }
+
+    /// Adds audit records of changes committed at this step, so records can be appended to
+    /// audit log on recovery.
+    pub fn with_audit(mut self, audit: Vec<AuditRecord>) -> JournalEntry {
+        self.audit = audit;
+        self
+    }
+
+    /// Replaces step of entry, other fields are kept.
+    pub fn with_step(mut self, step: JournalStep) -> JournalEntry {
+        self.step = step;
+        self
+    }
+
+    pub fn step(&self) -> JournalStep {
+        self.step
+    }
+
+    pub fn change(&self) -> u32 {
+        self.change
+    }
+
+    pub fn previous(&self) -> Option<u32> {
+        self.previous
+    }
+
+    pub fn pending_push(&self) -> bool {
+        self.pending_push
+    }
+
+    pub fn audit(&self) -> &[AuditRecord] {
+        &self.audit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use testing::temp_path;
+
+    #[test]
+    fn skipped_change_follows_commit() {
+        let directory = temp_path("journal");
+        fs::create_dir_all(directory.join(".hg")).unwrap();
+        let journal = Journal::new(&directory.display().to_string(), "main");
+
+        assert_eq!(journal.last_skipped(Some(10)).unwrap(), None);
+
+        journal.set_last_skipped(12, Some(10)).unwrap();
+
+        assert_eq!(journal.last_skipped(Some(10)).unwrap(), Some(12));
+        assert_eq!(journal.last_skipped(Some(8)).unwrap(), None);
+        assert_eq!(journal.last_skipped(None).unwrap(), None);
+
+        journal.set_last_skipped(3, None).unwrap();
+
+        assert_eq!(journal.last_skipped(None).unwrap(), Some(3));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn entry_round_trip() {
+        let directory = temp_path("entry");
+        fs::create_dir_all(directory.join(".hg")).unwrap();
+        let journal = Journal::new(&directory.display().to_string(), "feature/x");
+
+        assert!(journal.read().unwrap().is_none());
+
+        journal
+            .write(&JournalEntry::new(JournalStep::Commit, 12, Some(10), true))
+            .unwrap();
+
+        let entry = journal.read().unwrap().unwrap();
+
+        assert_eq!(entry.step(), JournalStep::Commit);
+        assert_eq!((entry.change(), entry.previous()), (12, Some(10)));
+        assert!(entry.pending_push());
+        assert!(entry.audit().is_empty());
+
+        journal.clear().unwrap();
+
+        assert!(journal.read().unwrap().is_none());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
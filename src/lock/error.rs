@@ -0,0 +1,55 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
+use std::io::Error as IoError;
+
+#[derive(Debug)]
+pub enum LockError {
+    IoError { message: String },
+    Locked { path: String, holder: String },
+}
+
+impl LockError {
+    pub fn io_error(error: IoError) -> LockError {
+        LockError::IoError {
+            message: format!("{}", error),
+        }
+    }
+
+    pub fn locked(path: &str, holder: &str) -> LockError {
+        LockError::Locked {
+            path: path.into(),
+            holder: holder.into(),
+        }
+    }
+}
+
+impl Display for LockError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            LockError::IoError { message } => write!(f, "IO error: {}", message),
+            LockError::Locked { path, holder } => match holder.rfind(':') {
+                Some(index) => write!(
+                    f,
+                    "Lock {} is held by process {} on host {}",
+                    path,
+                    &holder[index + 1..],
+                    &holder[..index]
+                ),
+                None => write!(f, "Lock {} is held by unknown process", path),
+            },
+        }
+    }
+}
+
+impl Error for LockError {
+    fn description(&self) -> &str {
+        match self {
+            LockError::IoError { .. } => "IO error",
+            LockError::Locked { .. } => "Locked",
+        }
+    }
+}
+
+pub type LockResult<T> = Result<T, LockError>;
@@ -0,0 +1,265 @@
+mod error;
+
+pub use self::error::LockError;
+pub use self::error::LockResult;
+
+use libc;
+
+use std::fs;
+use std::io::Error as IoError;
+use std::io::ErrorKind;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+
+/// Advisory lock in Mercurial lock format: symbolic link to `HOST:PID` of holder. Link is
+/// created atomically, so lock never exists without holder. Lock is released on drop.
+#[derive(Debug)]
+pub struct Lock {
+    path: PathBuf,
+    locker: String,
+}
+
+impl Lock {
+    /// Acquires lock, lock left by dead process on the same host is broken. Lock with holder
+    /// which can not be read is considered held.
+    pub fn acquire<P>(path: P) -> LockResult<Lock>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let locker = locker();
+
+        debug!("Acquiring lock {}.", path.display());
+        loop {
+            match symlink(&locker, path) {
+                Ok(()) => {
+                    debug!("Lock acquired.");
+                    return Ok(Lock {
+                        path: path.into(),
+                        locker,
+                    });
+                }
+                Err(ref error) if error.kind() == ErrorKind::AlreadyExists => {
+                    let holder = match read_locker(path)? {
+                        Some(holder) => holder,
+                        None => continue,
+                    };
+
+                    if !is_stale(&holder) {
+                        return Err(LockError::locked(&path.display().to_string(), &holder));
+                    }
+
+                    break_lock(path, &holder, &locker)?;
+                }
+                Err(error) => return Err(LockError::io_error(error)),
+            }
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        debug!("Releasing lock {}.", self.path.display());
+        match read_locker(&self.path) {
+            Ok(Some(ref holder)) if holder == &self.locker => {
+                if let Err(error) = fs::remove_file(&self.path) {
+                    warn!("Failed to release lock {}: {}", self.path.display(), error);
+                }
+            }
+            Ok(holder) => warn!(
+                "Lock {} was taken over, holder = {:?}.",
+                self.path.display(),
+                holder
+            ),
+            Err(error) => warn!("Failed to release lock {}: {}", self.path.display(), error),
+        }
+    }
+}
+
+/// Lock holder of this process, `HOST:PID`.
+pub fn locker() -> String {
+    format!("{}:{}", host_name(), process::id())
+}
+
+/// Removes stale lock. Lock is removed under `.break` lock only if it still has the same stale
+/// holder, so lock taken by another process in the meantime is kept.
+fn break_lock(path: &Path, stale: &str, locker: &str) -> LockResult<()> {
+    let mut break_path = path.as_os_str().to_owned();
+    break_path.push(".break");
+    let break_path = PathBuf::from(break_path);
+
+    match symlink(locker, &break_path) {
+        Ok(()) => {}
+        Err(ref error) if error.kind() == ErrorKind::AlreadyExists => {
+            if let Some(ref breaker) = read_locker(&break_path)? {
+                if is_stale(breaker) {
+                    warn!("Removing stale lock {}.", break_path.display());
+                    remove_if_exists(&break_path)?;
+                }
+            }
+
+            return Err(LockError::locked(&path.display().to_string(), stale));
+        }
+        Err(error) => return Err(LockError::io_error(error)),
+    }
+
+    let result = match read_locker(path) {
+        Ok(Some(ref holder)) if holder == stale => {
+            warn!(
+                "Removing stale lock {}, holder = {}.",
+                path.display(),
+                stale
+            );
+            remove_if_exists(path)
+        }
+        Ok(_) => Ok(()),
+        Err(error) => Err(error),
+    };
+
+    remove_if_exists(&break_path)?;
+
+    result
+}
+
+fn remove_if_exists(path: &Path) -> LockResult<()> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(ref error) if error.kind() == ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(LockError::io_error(error)),
+    }
+}
+
+/// Reads holder of lock, `None` if there is no lock. Lock which is not a symbolic link has
+/// empty holder.
+fn read_locker(path: &Path) -> LockResult<Option<String>> {
+    match fs::read_link(path) {
+        Ok(target) => Ok(Some(target.to_string_lossy().into_owned())),
+        Err(ref error) if error.kind() == ErrorKind::NotFound => Ok(None),
+        Err(ref error) if error.kind() == ErrorKind::InvalidInput => Ok(Some(String::new())),
+        Err(error) => Err(LockError::io_error(error)),
+    }
+}
+
+/// Splits holder into host name and PID. Mercurial may add PID namespace to host name as
+/// `HOST/NAMESPACE`, namespace is ignored.
+fn parse_locker(locker: &str) -> Option<(&str, u32)> {
+    let index = locker.rfind(':')?;
+    let host = locker[..index].split('/').next().unwrap_or_default();
+    let pid = locker[index + 1..].parse().ok()?;
+
+    if host.is_empty() {
+        None
+    } else {
+        Some((host, pid))
+    }
+}
+
+/// Lock is stale if it was taken by dead process on the same host.
+fn is_stale(locker: &str) -> bool {
+    match parse_locker(locker) {
+        Some((host, pid)) => host == host_name() && !is_alive(pid),
+        None => false,
+    }
+}
+
+fn is_alive(pid: u32) -> bool {
+    let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+
+    result == 0 || IoError::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+pub fn host_name() -> String {
+    let mut buffer = [0u8; 256];
+    let result =
+        unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+
+    if result == 0 {
+        let length = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+
+        String::from_utf8_lossy(&buffer[..length]).into_owned()
+    } else {
+        "localhost".into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process::Command;
+
+    use testing::temp_path;
+
+    fn lock_path(name: &str) -> PathBuf {
+        let path = temp_path(&format!("{}.lock", name));
+        let _ = fs::remove_file(&path);
+
+        path
+    }
+
+    fn dead_pid() -> u32 {
+        let mut child = Command::new("true").spawn().unwrap();
+        let pid = child.id();
+
+        child.wait().unwrap();
+        pid
+    }
+
+    #[test]
+    fn parses_locker() {
+        assert_eq!(parse_locker("host:42"), Some(("host", 42)));
+        assert_eq!(parse_locker("host/4026531836:42"), Some(("host", 42)));
+        assert_eq!(parse_locker("host:"), None);
+        assert_eq!(parse_locker(""), None);
+    }
+
+    #[test]
+    fn lock_is_exclusive_and_released_on_drop() {
+        let path = lock_path("exclusive");
+
+        {
+            let _lock = Lock::acquire(&path).unwrap();
+
+            assert_eq!(read_locker(&path).unwrap(), Some(locker()));
+            assert!(Lock::acquire(&path).is_err());
+        }
+
+        assert_eq!(read_locker(&path).unwrap(), None);
+    }
+
+    #[test]
+    fn stale_lock_is_taken_over() {
+        let path = lock_path("stale");
+
+        symlink(format!("{}:{}", host_name(), dead_pid()), &path).unwrap();
+
+        let lock = Lock::acquire(&path).unwrap();
+
+        assert_eq!(read_locker(&path).unwrap(), Some(locker()));
+        drop(lock);
+    }
+
+    #[test]
+    fn unreadable_lock_is_held() {
+        let path = lock_path("empty");
+
+        fs::write(&path, "").unwrap();
+
+        assert!(Lock::acquire(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn lock_of_other_host_is_held() {
+        let path = lock_path("remote");
+
+        symlink(format!("{}-other:{}", host_name(), dead_pid()), &path).unwrap();
+
+        assert!(Lock::acquire(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+use env_logger::Builder;
+use log::Record;
+use serde_json;
+
+use std::cell::RefCell;
+use std::io::Write;
+
+use config::LogFormat;
+
+thread_local! {
+    static CONTEXT: RefCell<LogContext> = RefCell::new(LogContext::default());
+}
+
+/// Context attached to every JSON log record of current thread.
+#[derive(Debug, Clone, Default)]
+struct LogContext {
+    mapping: Option<String>,
+    change: Option<u32>,
+    command: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct LogRecord<'a> {
+    timestamp: String,
+    level: String,
+    target: &'a str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mapping: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    change: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    command: Option<String>,
+}
+
+pub fn init(format: LogFormat) {
+    let mut builder = Builder::from_default_env();
+
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let line = format_json(&buf.timestamp().to_string(), record);
+
+            writeln!(buf, "{}", line)
+        });
+    }
+
+    builder.init();
+}
+
+pub fn set_mapping(mapping: Option<&str>) {
+    CONTEXT.with(|context| {
+        let mut context = context.borrow_mut();
+
+        context.mapping = mapping.map(Into::into);
+        context.change = None;
+    });
+}
+
+pub fn set_change(change: Option<u32>) {
+    CONTEXT.with(|context| context.borrow_mut().change = change);
+}
+
+pub fn set_command(command: Option<String>) {
+    CONTEXT.with(|context| context.borrow_mut().command = command);
+}
+
+fn format_json(timestamp: &str, record: &Record) -> String {
+    let context = CONTEXT.with(|context| context.borrow().clone());
+    let record = LogRecord {
+        timestamp: timestamp.into(),
+        level: record.level().to_string(),
+        target: record.target(),
+        message: record.args().to_string(),
+        mapping: context.mapping,
+        change: context.change,
+        command: context.command,
+    };
+
+    serde_json::to_string(&record).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use log::Level;
+    use serde_json::Value;
+
+    #[test]
+    fn formats_record_with_context() {
+        set_mapping(Some("main"));
+        set_change(Some(42));
+        set_command(Some("p4 sync".into()));
+
+        let line = format_json(
+            "2024-01-02T10:11:12Z",
+            &Record::builder()
+                .args(format_args!("Processing change {}", 42))
+                .level(Level::Info)
+                .target("perforce_sync::worker")
+                .build(),
+        );
+        let record: Value = serde_json::from_str(&line).unwrap();
+
+        assert_eq!(record["timestamp"], "2024-01-02T10:11:12Z");
+        assert_eq!(record["level"], "INFO");
+        assert_eq!(record["message"], "Processing change 42");
+        assert_eq!(record["mapping"], "main");
+        assert_eq!(record["change"], 42);
+        assert_eq!(record["command"], "p4 sync");
+
+        set_mapping(None);
+        set_command(None);
+
+        let line = format_json(
+            "2024-01-02T10:11:12Z",
+            &Record::builder()
+                .args(format_args!("Batch time = 1"))
+                .level(Level::Info)
+                .build(),
+        );
+
+        assert!(!line.contains("mapping"));
+        assert!(!line.contains("change"));
+    }
+}
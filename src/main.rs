@@ -1,40 +1,203 @@
+#[macro_use]
+extern crate lazy_static;
+
 #[macro_use]
 extern crate log;
 
 #[macro_use]
 extern crate serde_derive;
 
+extern crate encoding_rs;
 extern crate env_logger;
+extern crate libc;
+extern crate md5;
+extern crate regex;
+extern crate serde_json;
 extern crate serde_yaml;
 extern crate time;
 
 use std::env;
+use std::process;
+use std::sync::Arc;
 
+mod audit;
+mod authors;
 mod config;
+mod control;
+mod journal;
+mod lock;
+mod logging;
 mod mercurial;
+mod message;
+mod metrics;
+mod notify;
 mod perforce;
+mod server;
+mod status;
+#[cfg(test)]
+mod testing;
+mod transform;
+mod verify;
 mod worker;
 
 use config::Config;
+use control::Control;
+use control::ControlExit;
+use perforce::client_view;
+use status::SyncStatus;
 use worker::Worker;
 
 fn main() {
-    env_logger::init();
-
     let args: Vec<_> = env::args().skip(1).collect();
 
-    if args.len() == 0 {
-        error!("No configuration given. Use configuration file as single parameter.");
+    if args.is_empty() {
+        env_logger::init();
+        error!("No configuration given. Use: perforce-sync CONFIG [dry-run|status|view|verify|import-from|backfill|control].");
 
         return;
-    } else if args.len() > 1 {
-        error!("Too many configurations given. Use configuration file as single parameter.");
+    }
+
+    let config = Config::read(&args[0]).expect("config read failed");
+
+    logging::init(config.log_format());
+
+    match args[1..]
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        [] => run(&args[0], config),
+        ["dry-run"] => worker(&config).dry_run(),
+        ["status"] => print_status(&config),
+        ["view"] => print_view(&config),
+        ["verify", range @ ..] => verify(&config, range),
+        ["import-from", bookmark, change] => import_from(&config, bookmark, change),
+        ["backfill", bookmark] => {
+            if !worker(&config).backfill(bookmark) {
+                process::exit(1);
+            }
+        }
+        ["control", command @ ..] => send_control(&config, &command.join(" ")),
+        _ => error!(
+            "Unknown command given. Use: perforce-sync CONFIG [dry-run|status|view|verify|import-from|backfill|control]."
+        ),
+    }
+}
+
+fn worker(config: &Config) -> Worker<'_> {
+    let status = Arc::new(SyncStatus::new(config.mappings()));
+    let control = Arc::new(Control::new());
+
+    Worker::new(config, status, control)
+}
+
+fn run(path: &str, config: Config) {
+    let status = Arc::new(SyncStatus::new(config.mappings()));
+    let control = Arc::new(Control::new());
+
+    if let Some(http) = config.http() {
+        server::start(http.address(), status.clone()).expect("HTTP server start failed");
+    }
+
+    if let Some(socket) = config.socket() {
+        server::start_socket(socket, status.clone(), control.clone())
+            .expect("socket server start failed");
+    }
+
+    let mut config = config;
+
+    loop {
+        let exit = Worker::new(&config, status.clone(), control.clone()).start();
+
+        match exit {
+            ControlExit::Drain => {
+                info!("Drained, exiting.");
+
+                break;
+            }
+            ControlExit::Reload => match Config::read(path) {
+                Ok(new_config) => {
+                    info!("Configuration reloaded.");
+                    status.reset(new_config.mappings());
+                    config = new_config;
+                }
+                Err(err) => error!("Configuration reload failed: {}", err),
+            },
+        }
+    }
+}
+
+fn print_status(config: &Config) {
+    let response = if let Some(socket) = config.socket() {
+        server::socket_request(socket, "status")
+    } else if let Some(http) = config.http() {
+        server::http_request(http.address(), "/status")
+    } else {
+        error!("Neither socket nor http configured, status is not available.");
 
         return;
+    };
+
+    match response {
+        Ok(response) => println!("{}", response.trim()),
+        Err(err) => error!("Status request failed: {}", err),
     }
+}
 
-    let config = Config::read(&args[0]).expect("config read failed");
-    let worker = Worker::new(&config);
+fn print_view(config: &Config) {
+    let perforce = config.perforce();
+
+    match client_view(perforce.client(), perforce.work_dir(), config.mappings()) {
+        Ok(view) => {
+            for line in view {
+                println!("\t{}", line);
+            }
+        }
+        Err(err) => error!("View generation failed: {}", err),
+    }
+}
+
+fn import_from(config: &Config, bookmark: &str, change: &str) {
+    let imported = match change.parse() {
+        Ok(change) => worker(config).import_from(bookmark, change),
+        Err(_) => {
+            error!("Invalid change. Use: perforce-sync CONFIG import-from BOOKMARK CHANGE.");
+
+            false
+        }
+    };
 
-    worker.start();
+    if !imported {
+        process::exit(1);
+    }
+}
+
+fn verify(config: &Config, range: &[&str]) {
+    let parsed: Result<Vec<u32>, _> = range.iter().map(|change| change.parse()).collect();
+
+    let clean = match parsed.as_ref().map(Vec::as_slice) {
+        Ok([]) => worker(config).verify(None, None),
+        Ok([first]) => worker(config).verify(Some(*first), None),
+        Ok([first, last]) => worker(config).verify(Some(*first), Some(*last)),
+        _ => {
+            error!("Invalid change range. Use: perforce-sync CONFIG verify [FIRST [LAST]].");
+
+            false
+        }
+    };
+
+    if !clean {
+        process::exit(1);
+    }
+}
+
+fn send_control(config: &Config, command: &str) {
+    match config.socket() {
+        Some(socket) => match server::socket_request(socket, command) {
+            Ok(response) => println!("{}", response.trim()),
+            Err(err) => error!("Control request failed: {}", err),
+        },
+        None => error!("Socket is not configured, control is not available."),
+    }
 }
@@ -1,13 +1,24 @@
 use time::strftime;
 use time::Tm;
 
+use std::fs;
+use std::fs::Permissions;
 use std::io::BufRead;
 use std::io::BufReader;
+use std::io::ErrorKind;
 use std::io::Read;
+use std::os::unix::fs::symlink;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
 use std::path::PathBuf;
+use std::process;
 use std::process::Command;
+use std::process::ExitStatus;
 use std::process::Stdio;
 
+use message::parse_change;
+use metrics;
+
 use super::MercurialError;
 use super::MercurialResult;
 
@@ -15,6 +26,8 @@ use super::MercurialResult;
 pub struct MercurialClient {
     command: String,
     work_dir: String,
+    include: Vec<String>,
+    exclude: Vec<String>,
 }
 
 impl MercurialClient {
@@ -22,11 +35,21 @@ impl MercurialClient {
         MercurialClient {
             command: command.into(),
             work_dir: work_dir.into(),
+            include: Vec::new(),
+            exclude: Vec::new(),
         }
     }
 
+    /// Restricts status, addremove and commit to files matching include and not matching
+    /// exclude glob patterns.
+    pub fn set_filters(&mut self, include: &[String], exclude: &[String]) {
+        self.include = include.to_vec();
+        self.exclude = exclude.to_vec();
+    }
+
     pub fn update(&self, revision: &str) -> MercurialResult<()> {
         info!("Mercurial update, revision = {}.", revision);
+        let _timer = metrics::command_timer("hg", "update");
         let mut child = Command::new(&self.command)
             .current_dir(&self.work_dir)
             .env_clear()
@@ -50,16 +73,45 @@ impl MercurialClient {
         }
     }
 
+    pub fn update_clean(&self, revision: &str) -> MercurialResult<()> {
+        info!("Mercurial update clean, revision = {}.", revision);
+        let _timer = metrics::command_timer("hg", "update_clean");
+        let mut child = Command::new(&self.command)
+            .current_dir(&self.work_dir)
+            .env_clear()
+            .arg("update")
+            .arg("--clean")
+            .arg("--rev")
+            .arg(revision)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(MercurialError::execution_error)?;
+
+        let status = child.wait().map_err(MercurialError::io_error)?;
+
+        if status.success() {
+            debug!("update clean success.");
+            Ok(())
+        } else {
+            warn!("update clean failed.");
+            Err(MercurialError::exit_error(status.code()))
+        }
+    }
+
     pub fn last_commit(&self, revision: &str) -> MercurialResult<Option<u32>> {
         info!("Mercurial last commit, revision = {}.", revision);
+        let _timer = metrics::command_timer("hg", "last_commit");
         let mut child = Command::new(&self.command)
             .current_dir(&self.work_dir)
             .env_clear()
+            .env("HGENCODING", "utf-8")
             .arg("log")
             .arg("--rev")
             .arg(revision)
             .arg("--template")
-            .arg("{desc|firstline}")
+            .arg("{desc}")
             .stdin(Stdio::null())
             .stdout(Stdio::piped())
             .stderr(Stdio::null())
@@ -76,11 +128,7 @@ impl MercurialClient {
                 .read_to_string(&mut buffer)
                 .map_err(MercurialError::communication_error)?;
 
-            if buffer.starts_with("change #") {
-                result = Some(buffer[8..]
-                    .parse()
-                    .map_err(MercurialError::change_parse_error)?);
-            }
+            result = parse_change(&buffer).map_err(MercurialError::change_parse_error)?;
         }
 
         let status = child.wait().map_err(MercurialError::io_error)?;
@@ -94,14 +142,205 @@ impl MercurialClient {
         }
     }
 
+    /// Returns nodes and Perforce change numbers of all ancestors of revision.
+    pub fn changes(&self, revision: &str) -> MercurialResult<Vec<(String, u32)>> {
+        info!("Mercurial changes, revision = {}.", revision);
+        let _timer = metrics::command_timer("hg", "changes");
+        let mut child = Command::new(&self.command)
+            .current_dir(&self.work_dir)
+            .env_clear()
+            .env("HGENCODING", "utf-8")
+            .arg("log")
+            .arg("--rev")
+            .arg(format!("::{}", revision))
+            .arg("--template")
+            .arg("{node}\\n{desc}\\0")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(MercurialError::execution_error)?;
+
+        let mut result = Vec::new();
+
+        if let Some(ref mut stdout) = child.stdout {
+            debug!("Waiting for log output.");
+            let mut buffer = String::new();
+
+            stdout
+                .read_to_string(&mut buffer)
+                .map_err(MercurialError::communication_error)?;
+
+            for entry in buffer.split('\0').filter(|entry| !entry.is_empty()) {
+                let mut parts = entry.splitn(2, '\n');
+                let node = parts.next().unwrap_or_default();
+                let message = parts.next().unwrap_or_default();
+
+                if let Some(change) =
+                    parse_change(message).map_err(MercurialError::change_parse_error)?
+                {
+                    result.push((node.into(), change));
+                }
+            }
+        }
+
+        let status = child.wait().map_err(MercurialError::io_error)?;
+
+        if status.success() {
+            debug!("changes success.");
+            Ok(result)
+        } else {
+            warn!("changes failed.");
+            Err(MercurialError::exit_error(status.code()))
+        }
+    }
+
+    /// Returns files tracked in revision.
+    pub fn manifest(&self, revision: &str) -> MercurialResult<Vec<String>> {
+        info!("Mercurial manifest, revision = {}.", revision);
+        let _timer = metrics::command_timer("hg", "manifest");
+        let mut child = Command::new(&self.command)
+            .current_dir(&self.work_dir)
+            .env_clear()
+            .arg("manifest")
+            .arg("--rev")
+            .arg(revision)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(MercurialError::execution_error)?;
+
+        let mut result = Vec::new();
+
+        if let Some(ref mut stdout) = child.stdout {
+            let reader = BufReader::new(stdout);
+
+            for line in reader.lines() {
+                let line = line.map_err(MercurialError::communication_error)?;
+
+                result.push(line);
+            }
+        }
+
+        let status = child.wait().map_err(MercurialError::io_error)?;
+
+        if status.success() {
+            debug!("manifest success.");
+            Ok(result)
+        } else {
+            warn!("manifest failed.");
+            Err(MercurialError::exit_error(status.code()))
+        }
+    }
+
+    /// Writes files of revision into destination directory.
+    pub fn archive(&self, revision: &str, destination: &Path) -> MercurialResult<()> {
+        info!(
+            "Mercurial archive, revision = {}, destination = {}.",
+            revision,
+            destination.display()
+        );
+        let _timer = metrics::command_timer("hg", "archive");
+        let mut child = Command::new(&self.command)
+            .current_dir(&self.work_dir)
+            .env_clear()
+            .arg("archive")
+            .arg("--rev")
+            .arg(revision)
+            .arg("--type")
+            .arg("files")
+            .arg("--no-decode")
+            .arg(destination)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(MercurialError::execution_error)?;
+
+        let status = child.wait().map_err(MercurialError::io_error)?;
+
+        if status.success() {
+            debug!("archive success.");
+            Ok(())
+        } else {
+            warn!("archive failed.");
+            Err(MercurialError::exit_error(status.code()))
+        }
+    }
+
+    pub fn node(&self, revision: &str) -> MercurialResult<String> {
+        info!("Mercurial node, revision = {}.", revision);
+        let _timer = metrics::command_timer("hg", "node");
+        let mut child = Command::new(&self.command)
+            .current_dir(&self.work_dir)
+            .env_clear()
+            .env("HGENCODING", "utf-8")
+            .arg("log")
+            .arg("--rev")
+            .arg(revision)
+            .arg("--template")
+            .arg("{node}")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(MercurialError::execution_error)?;
+
+        let mut result = String::new();
+
+        if let Some(ref mut stdout) = child.stdout {
+            debug!("Waiting for log output.");
+            stdout
+                .read_to_string(&mut result)
+                .map_err(MercurialError::communication_error)?;
+        }
+
+        let status = child.wait().map_err(MercurialError::io_error)?;
+
+        if status.success() {
+            debug!("node success.");
+            Ok(result.trim().into())
+        } else {
+            warn!("node failed.");
+            Err(MercurialError::exit_error(status.code()))
+        }
+    }
+
     pub fn addremove(&self, similarity: u8) -> MercurialResult<()> {
         info!("Mercurial addremove, similarity = {}.", similarity);
+        let _timer = metrics::command_timer("hg", "addremove");
         let mut child = Command::new(&self.command)
             .current_dir(&self.work_dir)
             .env_clear()
             .arg("addremove")
             .arg("--similarity")
             .arg(format!("{}", similarity))
+            .args(self.filter_args())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(MercurialError::execution_error)?;
+
+        let status = child.wait().map_err(MercurialError::io_error)?;
+
+        if status.success() {
+            Ok(())
+        } else {
+            Err(MercurialError::exit_error(status.code()))
+        }
+    }
+
+    pub fn add(&self, path: &str) -> MercurialResult<()> {
+        info!("Mercurial add, path = {}.", path);
+        let _timer = metrics::command_timer("hg", "add");
+        let mut child = Command::new(&self.command)
+            .current_dir(&self.work_dir)
+            .env_clear()
+            .arg("add")
+            .arg("--")
+            .arg(path)
             .stdin(Stdio::null())
             .stdout(Stdio::null())
             .stderr(Stdio::null())
@@ -111,14 +350,17 @@ impl MercurialClient {
         let status = child.wait().map_err(MercurialError::io_error)?;
 
         if status.success() {
+            debug!("Add success.");
             Ok(())
         } else {
+            warn!("Add failed.");
             Err(MercurialError::exit_error(status.code()))
         }
     }
 
     pub fn add_large(&self, path: &str) -> MercurialResult<()> {
         info!("Mercurial add large, path = {}.", path);
+        let _timer = metrics::command_timer("hg", "add_large");
         let mut child = Command::new(&self.command)
             .current_dir(&self.work_dir)
             .env_clear()
@@ -144,12 +386,14 @@ impl MercurialClient {
 
     pub fn get_large_files(&self, min_size: u64) -> MercurialResult<Vec<String>> {
         info!("Mercurial add large files, min_size = {}.", min_size);
+        let _timer = metrics::command_timer("hg", "get_large_files");
         let mut child = Command::new(&self.command)
             .current_dir(&self.work_dir)
             .env_clear()
             .arg("status")
             .arg("--no-status")
             .arg("--unknown")
+            .args(self.filter_args())
             .stdin(Stdio::null())
             .stdout(Stdio::piped())
             .stderr(Stdio::null())
@@ -201,13 +445,204 @@ impl MercurialClient {
         }
     }
 
+    /// Returns given files which are ignored by `.hgignore`.
+    pub fn ignored(&self, paths: &[String]) -> MercurialResult<Vec<String>> {
+        info!("Mercurial ignored files, count = {}.", paths.len());
+        let _timer = metrics::command_timer("hg", "ignored");
+        let list_file = self.list_file(paths)?;
+        let result = self.read_ignored(&list_file);
+        let _ = fs::remove_file(&list_file);
+        let (result, status) = result?;
+
+        if status.success() {
+            debug!("Ignored files success.");
+            Ok(result)
+        } else {
+            warn!("Ignored files failed.");
+            Err(MercurialError::exit_error(status.code()))
+        }
+    }
+
+    fn read_ignored(&self, list_file: &Path) -> MercurialResult<(Vec<String>, ExitStatus)> {
+        let mut child = Command::new(&self.command)
+            .current_dir(&self.work_dir)
+            .env_clear()
+            .arg("status")
+            .arg("--no-status")
+            .arg("--ignored")
+            .args(self.filter_args())
+            .arg("--")
+            .arg(format!("listfile0:{}", list_file.display()))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(MercurialError::execution_error)?;
+
+        let mut result = Vec::new();
+
+        debug!("Reading ignored files.");
+        if let Some(ref mut stdout) = child.stdout {
+            let reader = BufReader::new(stdout);
+
+            for line in reader.lines() {
+                debug!("Reading file {:?}.", line);
+                let line = line.map_err(MercurialError::communication_error)?;
+
+                result.push(line);
+            }
+        }
+
+        let status = child.wait().map_err(MercurialError::io_error)?;
+
+        Ok((result, status))
+    }
+
+    /// Lists unknown files of working copy, files excluded by filters are not listed.
+    pub fn unknown(&self) -> MercurialResult<Vec<String>> {
+        info!("Mercurial unknown files.");
+        let _timer = metrics::command_timer("hg", "unknown");
+        let mut child = Command::new(&self.command)
+            .current_dir(&self.work_dir)
+            .env_clear()
+            .arg("status")
+            .arg("--no-status")
+            .arg("--unknown")
+            .args(self.filter_args())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(MercurialError::execution_error)?;
+
+        let mut result = Vec::new();
+
+        debug!("Reading unknown files.");
+        if let Some(ref mut stdout) = child.stdout {
+            let reader = BufReader::new(stdout);
+
+            for line in reader.lines() {
+                debug!("Reading file {:?}.", line);
+                let line = line.map_err(MercurialError::communication_error)?;
+
+                result.push(line);
+            }
+        }
+
+        let status = child.wait().map_err(MercurialError::io_error)?;
+
+        if status.success() {
+            debug!("Unknown files success.");
+            Ok(result)
+        } else {
+            warn!("Unknown files failed.");
+            Err(MercurialError::exit_error(status.code()))
+        }
+    }
+
+    /// Deletes file of working copy which is not tracked by Mercurial.
+    pub fn remove_unknown(&self, path: &str) -> MercurialResult<()> {
+        let mut file_path = PathBuf::new();
+        file_path.push(&self.work_dir);
+        file_path.push(path);
+
+        fs::remove_file(&file_path).map_err(MercurialError::io_error)
+    }
+
+    /// Reverts tracked files to content of working copy parent without backups.
+    pub fn revert(&self, paths: &[String]) -> MercurialResult<()> {
+        if paths.is_empty() {
+            return Ok(());
+        }
+
+        info!("Mercurial revert, count = {}.", paths.len());
+        let _timer = metrics::command_timer("hg", "revert");
+        let list_file = self.list_file(paths)?;
+        let result = Command::new(&self.command)
+            .current_dir(&self.work_dir)
+            .env_clear()
+            .arg("revert")
+            .arg("--no-backup")
+            .arg("--")
+            .arg(format!("listfile0:{}", list_file.display()))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        let _ = fs::remove_file(&list_file);
+        let status = result.map_err(MercurialError::execution_error)?;
+
+        if status.success() {
+            debug!("Revert success.");
+            Ok(())
+        } else {
+            warn!("Revert failed.");
+            Err(MercurialError::exit_error(status.code()))
+        }
+    }
+
+    /// Makes executable bit and symlink of working copy file match given flags, so they are
+    /// recorded by next commit. Returns `true` if file was changed, missing file is left as is.
+    pub fn ensure_flags(&self, path: &str, executable: bool, link: bool) -> MercurialResult<bool> {
+        let mut file_path = PathBuf::new();
+        file_path.push(&self.work_dir);
+        file_path.push(path);
+
+        let metadata = match fs::symlink_metadata(&file_path) {
+            Ok(metadata) => metadata,
+            Err(ref error) if error.kind() == ErrorKind::NotFound => {
+                debug!("File {} is not in working copy.", path);
+
+                return Ok(false);
+            }
+            Err(error) => return Err(MercurialError::read_metadata_error(error)),
+        };
+
+        if metadata.file_type().is_symlink() {
+            if !link {
+                warn!("File {} is symlink in working copy only.", path);
+            }
+
+            return Ok(false);
+        }
+
+        if link {
+            debug!("Replacing {} with symlink.", path);
+            let target = fs::read_to_string(&file_path).map_err(MercurialError::io_error)?;
+
+            fs::remove_file(&file_path).map_err(MercurialError::io_error)?;
+            symlink(target.trim_end_matches('\n'), &file_path).map_err(MercurialError::io_error)?;
+
+            return Ok(true);
+        }
+
+        let mode = metadata.permissions().mode();
+        let new_mode = if executable {
+            mode | ((mode & 0o444) >> 2)
+        } else {
+            mode & !0o111
+        };
+
+        if new_mode == mode {
+            return Ok(false);
+        }
+
+        debug!("Changing mode of {} to {:o}.", path, new_mode);
+        fs::set_permissions(&file_path, Permissions::from_mode(new_mode))
+            .map_err(MercurialError::io_error)?;
+
+        Ok(true)
+    }
+
     pub fn status(&self) -> MercurialResult<Vec<String>> {
         info!("Mercurial status.");
+        let _timer = metrics::command_timer("hg", "status");
         let mut child = Command::new(&self.command)
             .current_dir(&self.work_dir)
             .env_clear()
             .arg("status")
             .arg("--no-status")
+            .args(self.filter_args())
             .stdin(Stdio::null())
             .stdout(Stdio::piped())
             .stderr(Stdio::null())
@@ -243,16 +678,22 @@ impl MercurialClient {
 
     pub fn commit(&self, message: &str, date: &Tm, user: &str) -> MercurialResult<()> {
         info!("Mercurial commit, user = {}.", user);
+        let _timer = metrics::command_timer("hg", "commit");
         let mut child = Command::new(&self.command)
             .current_dir(&self.work_dir)
             .env_clear()
+            .env("HGENCODING", "utf-8")
             .arg("commit")
             .arg("--message")
             .arg(message)
             .arg("--date")
-            .arg(strftime("%Y-%m-%d %H:%M:%S", date).map_err(MercurialError::date_format_error)?)
+            .arg(
+                strftime("%Y-%m-%d %H:%M:%S %z", date)
+                    .map_err(MercurialError::date_format_error)?,
+            )
             .arg("--user")
             .arg(user)
+            .args(self.filter_args())
             .stdin(Stdio::null())
             .stdout(Stdio::null())
             .stderr(Stdio::null())
@@ -272,6 +713,7 @@ impl MercurialClient {
 
     pub fn push(&self) -> MercurialResult<()> {
         info!("Mercurial push.");
+        let _timer = metrics::command_timer("hg", "push");
         let mut child = Command::new(&self.command)
             .current_dir(&self.work_dir)
             .env_clear()
@@ -292,4 +734,291 @@ impl MercurialClient {
             Err(MercurialError::exit_error(status.code()))
         }
     }
+
+    pub fn bookmarks(&self) -> MercurialResult<Vec<String>> {
+        info!("Mercurial bookmarks.");
+        let _timer = metrics::command_timer("hg", "bookmarks");
+        let mut child = Command::new(&self.command)
+            .current_dir(&self.work_dir)
+            .env_clear()
+            .env("HGENCODING", "utf-8")
+            .arg("bookmarks")
+            .arg("--template")
+            .arg("{bookmark}\n")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(MercurialError::execution_error)?;
+
+        let mut result = Vec::new();
+
+        if let Some(ref mut stdout) = child.stdout {
+            let reader = BufReader::new(stdout);
+
+            for line in reader.lines() {
+                let line = line.map_err(MercurialError::communication_error)?;
+
+                result.push(line);
+            }
+        }
+
+        let status = child.wait().map_err(MercurialError::io_error)?;
+
+        if status.success() {
+            debug!("bookmarks success.");
+            Ok(result)
+        } else {
+            warn!("bookmarks failed.");
+            Err(MercurialError::exit_error(status.code()))
+        }
+    }
+
+    /// Creates bookmark at working copy parent and makes it active, so following commits move
+    /// it.
+    pub fn bookmark(&self, name: &str) -> MercurialResult<()> {
+        info!("Mercurial bookmark, name = {}.", name);
+        let _timer = metrics::command_timer("hg", "bookmark");
+        let mut child = Command::new(&self.command)
+            .current_dir(&self.work_dir)
+            .env_clear()
+            .env("HGENCODING", "utf-8")
+            .arg("bookmark")
+            .arg("--")
+            .arg(name)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(MercurialError::execution_error)?;
+
+        let status = child.wait().map_err(MercurialError::io_error)?;
+
+        if status.success() {
+            debug!("Bookmark success.");
+            Ok(())
+        } else {
+            warn!("Bookmark failed.");
+            Err(MercurialError::exit_error(status.code()))
+        }
+    }
+
+    /// Creates bookmark at revision, bookmark is not activated. Bookmark is moved by commits
+    /// only after `update` to it.
+    pub fn set_bookmark(&self, name: &str, revision: &str) -> MercurialResult<()> {
+        info!(
+            "Mercurial set bookmark, name = {}, revision = {}.",
+            name, revision
+        );
+        let _timer = metrics::command_timer("hg", "set_bookmark");
+        let mut child = Command::new(&self.command)
+            .current_dir(&self.work_dir)
+            .env_clear()
+            .env("HGENCODING", "utf-8")
+            .arg("bookmark")
+            .arg("--rev")
+            .arg(revision)
+            .arg("--")
+            .arg(name)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(MercurialError::execution_error)?;
+
+        let status = child.wait().map_err(MercurialError::io_error)?;
+
+        if status.success() {
+            debug!("Set bookmark success.");
+            Ok(())
+        } else {
+            warn!("Set bookmark failed.");
+            Err(MercurialError::exit_error(status.code()))
+        }
+    }
+
+    /// Tags revision, existing tag is moved. Tag is committed on top of working copy parent.
+    pub fn tag(
+        &self,
+        name: &str,
+        revision: &str,
+        message: &str,
+        user: &str,
+    ) -> MercurialResult<()> {
+        info!("Mercurial tag, name = {}, revision = {}.", name, revision);
+        let _timer = metrics::command_timer("hg", "tag");
+        let mut child = Command::new(&self.command)
+            .current_dir(&self.work_dir)
+            .env_clear()
+            .env("HGENCODING", "utf-8")
+            .arg("tag")
+            .arg("--force")
+            .arg("--rev")
+            .arg(revision)
+            .arg("--message")
+            .arg(message)
+            .arg("--user")
+            .arg(user)
+            .arg("--")
+            .arg(name)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(MercurialError::execution_error)?;
+
+        let status = child.wait().map_err(MercurialError::io_error)?;
+
+        if status.success() {
+            debug!("Tag success.");
+            Ok(())
+        } else {
+            warn!("Tag failed.");
+            Err(MercurialError::exit_error(status.code()))
+        }
+    }
+
+    /// Pushes bookmark even if it creates new head on server.
+    pub fn push_bookmark(&self, name: &str) -> MercurialResult<()> {
+        info!("Mercurial push bookmark, name = {}.", name);
+        let _timer = metrics::command_timer("hg", "push_bookmark");
+        let mut child = Command::new(&self.command)
+            .current_dir(&self.work_dir)
+            .env_clear()
+            .arg("push")
+            .arg("--force")
+            .arg("--bookmark")
+            .arg(name)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(MercurialError::execution_error)?;
+
+        let status = child.wait().map_err(MercurialError::io_error)?;
+
+        if status.success() {
+            debug!("Push bookmark success.");
+            Ok(())
+        } else {
+            warn!("Push bookmark failed.");
+            Err(MercurialError::exit_error(status.code()))
+        }
+    }
+
+    /// Writes paths separated by NUL into file in `.hg` directory, file is passed to Mercurial as
+    /// `listfile0:` pattern, so number of paths is not limited by command length.
+    fn list_file(&self, paths: &[String]) -> MercurialResult<PathBuf> {
+        let mut path = PathBuf::new();
+        path.push(&self.work_dir);
+        path.push(".hg");
+        path.push(format!("perforce-sync-{}.list", process::id()));
+
+        let mut content = Vec::new();
+
+        for file in paths {
+            content.extend_from_slice(file.as_bytes());
+            content.push(0);
+        }
+
+        fs::write(&path, content).map_err(MercurialError::io_error)?;
+
+        Ok(path)
+    }
+
+    fn filter_args(&self) -> Vec<String> {
+        let include = self
+            .include
+            .iter()
+            .flat_map(|pattern| vec!["--include".into(), format!("glob:{}", pattern)]);
+        let exclude = self
+            .exclude
+            .iter()
+            .flat_map(|pattern| vec!["--exclude".into(), format!("glob:{}", pattern)]);
+
+        include.chain(exclude).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use testing::temp_path;
+
+    fn work_dir(name: &str) -> PathBuf {
+        let path = temp_path(name);
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        path
+    }
+
+    #[test]
+    fn ensures_flags() {
+        let path = work_dir("flags");
+        let client = MercurialClient::new("hg", &path.display().to_string());
+
+        fs::write(path.join("script"), "#!/bin/sh\n").unwrap();
+        fs::set_permissions(path.join("script"), Permissions::from_mode(0o644)).unwrap();
+        fs::write(path.join("link"), "script\n").unwrap();
+
+        assert!(client.ensure_flags("script", true, false).unwrap());
+        assert!(!client.ensure_flags("script", true, false).unwrap());
+        assert_eq!(
+            fs::metadata(path.join("script"))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o777,
+            0o755
+        );
+        assert!(client.ensure_flags("link", false, true).unwrap());
+        assert_eq!(
+            fs::read_link(path.join("link")).unwrap(),
+            PathBuf::from("script")
+        );
+        assert!(!client.ensure_flags("missing", true, false).unwrap());
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn active_bookmark_follows_commits() {
+        let path = work_dir("bookmark");
+        let client = MercurialClient::new("hg", &path.display().to_string());
+        let date = time::at_utc(time::Timespec::new(1_500_000_000, 0));
+
+        // Mercurial is not installed everywhere tests are run.
+        match Command::new("hg").arg("init").arg(&path).status() {
+            Ok(status) if status.success() => {}
+            _ => return,
+        }
+
+        client.update_clean("null").unwrap();
+
+        for change in 1..4 {
+            fs::write(path.join(format!("file{}", change)), "content\n").unwrap();
+            client.addremove(80).unwrap();
+            client
+                .commit(&format!("change #{}\nChange", change), &date, "user")
+                .unwrap();
+
+            if change == 1 {
+                client.bookmark("main-history").unwrap();
+                client
+                    .set_bookmark("main-root", &client.node(".").unwrap())
+                    .unwrap();
+            }
+        }
+
+        assert_eq!(client.last_commit("main-history").unwrap(), Some(3));
+        assert_eq!(
+            client.node("main-history").unwrap(),
+            client.node(".").unwrap()
+        );
+        assert_eq!(client.last_commit("main-root").unwrap(), Some(1));
+
+        fs::remove_dir_all(&path).unwrap();
+    }
 }
@@ -0,0 +1,242 @@
+use time::strftime;
+
+use std::num::ParseIntError;
+
+use perforce::Change;
+
+const LEGACY_PREFIX: &str = "change #";
+const TRAILER_PREFIX: &str = "[perforce-sync: change = ";
+const TRAILER_SUFFIX: &str = "]";
+
+/// Formats commit message for Perforce change. Without template message starts with
+/// `change #N` line followed by description. With template rendered message is followed by
+/// trailer line `[perforce-sync: change = N]` which is used to resume synchronization.
+pub fn format_change(change: &Change, depot_path: &str, template: Option<&str>) -> String {
+    match template {
+        Some(template) => {
+            let mut message = render(template, change, depot_path);
+
+            message.push_str(&format!(
+                "\n\n{}{}{}",
+                TRAILER_PREFIX,
+                change.change(),
+                TRAILER_SUFFIX
+            ));
+            message
+        }
+        None => format!(
+            "{}{}\n{}",
+            LEGACY_PREFIX,
+            change.change(),
+            change.description()
+        ),
+    }
+}
+
+/// Formats commit message for snapshot of depot path at `head` change. Message lists included
+/// changes and ends with trailer of head change, so synchronization resumes after it.
+pub fn format_snapshot(changes: &[Change], depot_path: &str, head: u32) -> String {
+    let title = format!("Snapshot of {} at change {}", depot_path, head);
+
+    format_combined(&title, changes, head)
+}
+
+/// Formats commit message for group of consecutive changes imported as one commit. Message lists
+/// all changes of group and ends with trailer of the last change.
+pub fn format_group(changes: &[Change], depot_path: &str) -> String {
+    let numbers: Vec<_> = changes
+        .iter()
+        .map(|change| change.change().to_string())
+        .collect();
+    let head = changes.last().map_or(0, Change::change);
+    let title = format!("Changes {} of {}", numbers.join(", "), depot_path);
+
+    format_combined(&title, changes, head)
+}
+
+fn format_combined(title: &str, changes: &[Change], head: u32) -> String {
+    let mut message = String::from(title);
+
+    if !changes.is_empty() {
+        message.push('\n');
+    }
+
+    for change in changes {
+        message.push_str(&format!(
+            "\n{} {}: {}",
+            change.change(),
+            change.user(),
+            change.description().lines().next().unwrap_or_default()
+        ));
+    }
+
+    message.push_str(&format!("\n\n{}{}{}", TRAILER_PREFIX, head, TRAILER_SUFFIX));
+    message
+}
+
+/// Formats message of commit linking backfilled history before `start` change. Message ends with
+/// trailer of `head` change, so synchronization of mapping is not affected by the commit.
+pub fn format_link(depot_path: &str, start: u32, head: u32) -> String {
+    format!(
+        "Link history of {} before change {}\n\n{}{}{}",
+        depot_path, start, TRAILER_PREFIX, head, TRAILER_SUFFIX
+    )
+}
+
+/// Extracts Perforce change number from commit message. Trailer is looked up first, so
+/// rendered template starting with `change #` does not hide it, legacy first line is used only
+/// for messages without trailer.
+pub fn parse_change(message: &str) -> Result<Option<u32>, ParseIntError> {
+    for line in message.lines().rev() {
+        let number = line
+            .trim()
+            .strip_prefix(TRAILER_PREFIX)
+            .and_then(|rest| rest.strip_suffix(TRAILER_SUFFIX));
+
+        if let Some(number) = number {
+            return number.parse().map(Some);
+        }
+    }
+
+    if let Some(rest) = message.strip_prefix(LEGACY_PREFIX) {
+        let number = rest.lines().next().unwrap_or_default();
+
+        return number.trim().parse().map(Some);
+    }
+
+    Ok(None)
+}
+
+fn render(template: &str, change: &Change, depot_path: &str) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let value = match rest.find('}') {
+            Some(end) => placeholder(&rest[1..end], change, depot_path).map(|value| (value, end)),
+            None => None,
+        };
+
+        match value {
+            Some((value, end)) => {
+                result.push_str(&value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+fn placeholder(name: &str, change: &Change, depot_path: &str) -> Option<String> {
+    match name {
+        "change" => Some(change.change().to_string()),
+        "description" => Some(change.description().trim_end().into()),
+        "first_line" => Some(
+            change
+                .description()
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .into(),
+        ),
+        "user" => Some(change.user().into()),
+        "client" => Some(change.client().into()),
+        "date" => strftime("%Y/%m/%d %H:%M:%S", change.date()).ok(),
+        "jobs" => Some(change.jobs().join(", ")),
+        "depot_path" => Some(depot_path.into()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use time::at_utc;
+    use time::Timespec;
+
+    fn change(number: u32, description: &str) -> Change {
+        Change::new(
+            number,
+            at_utc(Timespec::new(1_500_000_000, 0)),
+            "user",
+            "client",
+            description,
+            vec!["JOB-1".into()],
+        )
+    }
+
+    #[test]
+    fn change_round_trip() {
+        let change = change(42, "Fix build\n\nDetails\n");
+
+        assert_eq!(
+            parse_change(&format_change(&change, "//depot/main", None)),
+            Ok(Some(42))
+        );
+        assert_eq!(
+            parse_change(&format_change(
+                &change,
+                "//depot/main",
+                Some("{first_line}")
+            )),
+            Ok(Some(42))
+        );
+    }
+
+    #[test]
+    fn trailer_wins_over_legacy_prefix() {
+        let change = change(42, "Fix build");
+        let message = format_change(&change, "//depot/main", Some("change #7 {description}"));
+
+        assert_eq!(parse_change(&message), Ok(Some(42)));
+    }
+
+    #[test]
+    fn combined_round_trip() {
+        let changes = vec![change(41, "First"), change(42, "Second")];
+
+        assert_eq!(
+            parse_change(&format_group(&changes, "//depot/main")),
+            Ok(Some(42))
+        );
+        assert_eq!(
+            parse_change(&format_snapshot(&changes, "//depot/main", 43)),
+            Ok(Some(43))
+        );
+        assert_eq!(
+            parse_change(&format_link("//depot/main", 40, 43)),
+            Ok(Some(43))
+        );
+    }
+
+    #[test]
+    fn message_without_change() {
+        assert_eq!(parse_change("Manual commit"), Ok(None));
+        assert!(parse_change("change #x").is_err());
+    }
+
+    #[test]
+    fn renders_template() {
+        let change = change(42, "Fix build\n\nDetails\n");
+        let message = render(
+            "{change} {user}@{client} {jobs} {depot_path} {unknown} {first_line}",
+            &change,
+            "//depot/main",
+        );
+
+        assert_eq!(
+            message,
+            "42 user@client JOB-1 //depot/main {unknown} Fix build"
+        );
+    }
+}
@@ -0,0 +1,278 @@
+use time::get_time;
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use logging;
+
+const BUCKETS: [f64; 9] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+lazy_static! {
+    static ref METRICS: Mutex<Metrics> = Mutex::new(Metrics::default());
+}
+
+#[derive(Debug, Default)]
+struct Metrics {
+    imported_changes: BTreeMap<String, u64>,
+    lag_changes: BTreeMap<String, u64>,
+    lag_seconds: BTreeMap<String, i64>,
+    last_push: BTreeMap<String, i64>,
+    failures: BTreeMap<(String, String), u64>,
+    commands: BTreeMap<(String, String), Histogram>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [u64; 9],
+    count: u64,
+    sum: f64,
+}
+
+/// Records command duration to latency histogram on drop. Command is attached to log records
+/// while timer is alive.
+#[derive(Debug)]
+pub struct CommandTimer {
+    tool: &'static str,
+    command: &'static str,
+    start: Instant,
+}
+
+impl Drop for CommandTimer {
+    fn drop(&mut self) {
+        logging::set_command(None);
+
+        let elapsed = self.start.elapsed();
+        let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+
+        with_metrics(|metrics| {
+            metrics
+                .commands
+                .entry((self.tool.into(), self.command.into()))
+                .or_insert_with(Histogram::default)
+                .observe(seconds)
+        });
+    }
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(BUCKETS.iter()) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+pub fn command_timer(tool: &'static str, command: &'static str) -> CommandTimer {
+    logging::set_command(Some(format!("{} {}", tool, command)));
+
+    CommandTimer {
+        tool,
+        command,
+        start: Instant::now(),
+    }
+}
+
+pub fn change_imported(mapping: &str) {
+    with_metrics(|metrics| *metrics.imported_changes.entry(mapping.into()).or_insert(0) += 1);
+}
+
+pub fn set_lag(mapping: &str, changes: u64, seconds: i64) {
+    with_metrics(|metrics| {
+        metrics.lag_changes.insert(mapping.into(), changes);
+        metrics.lag_seconds.insert(mapping.into(), seconds);
+    });
+}
+
+pub fn pushed(mapping: &str) {
+    let now = get_time().sec;
+
+    with_metrics(|metrics| {
+        metrics.last_push.insert(mapping.into(), now);
+    });
+}
+
+pub fn failed(mapping: &str, kind: &str) {
+    with_metrics(|metrics| {
+        *metrics
+            .failures
+            .entry((mapping.into(), kind.into()))
+            .or_insert(0) += 1
+    });
+}
+
+/// Renders all metrics in Prometheus text exposition format.
+pub fn render() -> String {
+    let mut result = String::new();
+
+    with_metrics(|metrics| {
+        write_family(
+            &mut result,
+            "perforce_sync_imported_changes_total",
+            "counter",
+            "Number of imported Perforce changes.",
+            metrics.imported_changes.iter(),
+        );
+        write_family(
+            &mut result,
+            "perforce_sync_lag_changes",
+            "gauge",
+            "Number of Perforce changes not imported yet.",
+            metrics.lag_changes.iter(),
+        );
+        write_family(
+            &mut result,
+            "perforce_sync_lag_seconds",
+            "gauge",
+            "Age of oldest Perforce change not imported yet.",
+            metrics.lag_seconds.iter(),
+        );
+        write_family(
+            &mut result,
+            "perforce_sync_last_push_timestamp_seconds",
+            "gauge",
+            "Time of last successful push to Mercurial server.",
+            metrics.last_push.iter(),
+        );
+
+        writeln!(
+            result,
+            "# HELP perforce_sync_failures_total Number of mapping failures."
+        )
+        .ok();
+        writeln!(result, "# TYPE perforce_sync_failures_total counter").ok();
+
+        for ((mapping, kind), value) in &metrics.failures {
+            writeln!(
+                result,
+                "perforce_sync_failures_total{{mapping=\"{}\",kind=\"{}\"}} {}",
+                escape(mapping),
+                escape(kind),
+                value
+            )
+            .ok();
+        }
+
+        writeln!(
+            result,
+            "# HELP perforce_sync_command_duration_seconds Duration of p4 and hg commands."
+        )
+        .ok();
+        writeln!(
+            result,
+            "# TYPE perforce_sync_command_duration_seconds histogram"
+        )
+        .ok();
+
+        for ((tool, command), histogram) in &metrics.commands {
+            let labels = format!("tool=\"{}\",command=\"{}\"", tool, command);
+
+            for (bucket, bound) in histogram.buckets.iter().zip(BUCKETS.iter()) {
+                writeln!(
+                    result,
+                    "perforce_sync_command_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, bucket
+                )
+                .ok();
+            }
+
+            writeln!(
+                result,
+                "perforce_sync_command_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, histogram.count
+            )
+            .ok();
+            writeln!(
+                result,
+                "perforce_sync_command_duration_seconds_sum{{{}}} {}",
+                labels, histogram.sum
+            )
+            .ok();
+            writeln!(
+                result,
+                "perforce_sync_command_duration_seconds_count{{{}}} {}",
+                labels, histogram.count
+            )
+            .ok();
+        }
+    });
+
+    result
+}
+
+fn with_metrics<F, T>(callback: F) -> T
+where
+    F: FnOnce(&mut Metrics) -> T,
+{
+    let mut metrics = match METRICS.lock() {
+        Ok(metrics) => metrics,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+
+    callback(&mut metrics)
+}
+
+fn write_family<'a, I, T>(result: &mut String, name: &str, kind: &str, help: &str, values: I)
+where
+    I: Iterator<Item = (&'a String, &'a T)>,
+    T: Display + 'a,
+{
+    writeln!(result, "# HELP {} {}", name, help).ok();
+    writeln!(result, "# TYPE {} {}", name, kind).ok();
+
+    for (mapping, value) in values {
+        writeln!(
+            result,
+            "{}{{mapping=\"{}\"}} {}",
+            name,
+            escape(mapping),
+            value
+        )
+        .ok();
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_mapping_metrics() {
+        change_imported("metrics-test");
+        change_imported("metrics-test");
+        set_lag("metrics-test", 3, 120);
+        failed("metrics \"quoted\"", "Perforce error");
+        drop(command_timer("test", "render"));
+
+        let rendered = render();
+
+        assert!(
+            rendered.contains("perforce_sync_imported_changes_total{mapping=\"metrics-test\"} 2\n")
+        );
+        assert!(rendered.contains("perforce_sync_lag_changes{mapping=\"metrics-test\"} 3\n"));
+        assert!(rendered.contains("perforce_sync_lag_seconds{mapping=\"metrics-test\"} 120\n"));
+        assert!(rendered.contains(
+            "perforce_sync_failures_total{mapping=\"metrics \\\"quoted\\\"\",kind=\"Perforce error\"} 1\n"
+        ));
+        assert!(rendered.contains(
+            "perforce_sync_command_duration_seconds_bucket{tool=\"test\",command=\"render\",le=\"0.1\"} 1\n"
+        ));
+        assert!(rendered.contains(
+            "perforce_sync_command_duration_seconds_count{tool=\"test\",command=\"render\"} 1\n"
+        ));
+    }
+}
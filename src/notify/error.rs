@@ -0,0 +1,67 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
+use std::io::Error as IoError;
+
+use serde_json::Error as JsonError;
+
+#[derive(Debug)]
+pub enum NotifyError {
+    IoError { message: String },
+    SerializationError { message: String },
+    InvalidUrl { url: String },
+    UnexpectedResponse { response: String },
+}
+
+impl NotifyError {
+    pub fn io_error(error: IoError) -> NotifyError {
+        NotifyError::IoError {
+            message: format!("{}", error),
+        }
+    }
+
+    pub fn serialization_error(error: JsonError) -> NotifyError {
+        NotifyError::SerializationError {
+            message: format!("{}", error),
+        }
+    }
+
+    pub fn invalid_url(url: &str) -> NotifyError {
+        NotifyError::InvalidUrl { url: url.into() }
+    }
+
+    pub fn unexpected_response(response: &str) -> NotifyError {
+        NotifyError::UnexpectedResponse {
+            response: response.into(),
+        }
+    }
+}
+
+impl Display for NotifyError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            NotifyError::IoError { message } => write!(f, "IO error: {}", message),
+            NotifyError::SerializationError { message } => {
+                write!(f, "Serialization error: {}", message)
+            }
+            NotifyError::InvalidUrl { url } => write!(f, "Invalid URL: {}", url),
+            NotifyError::UnexpectedResponse { response } => {
+                write!(f, "Unexpected response: {}", response)
+            }
+        }
+    }
+}
+
+impl Error for NotifyError {
+    fn description(&self) -> &str {
+        match self {
+            NotifyError::IoError { .. } => "IO error",
+            NotifyError::SerializationError { .. } => "Serialization error",
+            NotifyError::InvalidUrl { .. } => "Invalid URL",
+            NotifyError::UnexpectedResponse { .. } => "Unexpected response",
+        }
+    }
+}
+
+pub type NotifyResult<T> = Result<T, NotifyError>;
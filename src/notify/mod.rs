@@ -0,0 +1,293 @@
+mod error;
+mod smtp;
+mod webhook;
+
+pub use self::error::NotifyError;
+pub use self::error::NotifyResult;
+
+use serde_json;
+use time::now_utc;
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::time::Duration;
+use std::time::Instant;
+
+use config::NotificationsConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Event {
+    Failing,
+    Recovered,
+    Lagging,
+}
+
+#[derive(Debug, Serialize)]
+struct WebhookBody<'a> {
+    mapping: &'a str,
+    event: Event,
+    message: &'a str,
+    timestamp: String,
+}
+
+/// Sends notifications when mapping starts failing, recovers or falls behind Perforce.
+/// Notifications of the same event for the same mapping are sent not more often than
+/// configured interval.
+#[derive(Debug)]
+pub struct Notifier<'a> {
+    config: Option<&'a NotificationsConfig>,
+    failing: RefCell<BTreeSet<String>>,
+    lagging: RefCell<BTreeSet<String>>,
+    last_sent: RefCell<BTreeMap<(String, Event), Instant>>,
+}
+
+impl<'a> Notifier<'a> {
+    pub fn new(config: Option<&'a NotificationsConfig>) -> Notifier<'a> {
+        Notifier {
+            config,
+            failing: RefCell::new(BTreeSet::new()),
+            lagging: RefCell::new(BTreeSet::new()),
+            last_sent: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn succeeded(&self, mapping: &str, lag_changes: u64) {
+        let config = match self.config {
+            Some(config) => config,
+            None => return,
+        };
+
+        if self.failing.borrow().contains(mapping)
+            && self.notify(
+                config,
+                mapping,
+                Event::Recovered,
+                &format!("Mapping {} recovered.", mapping),
+            )
+        {
+            self.failing.borrow_mut().remove(mapping);
+        }
+
+        match config.lag_threshold() {
+            Some(threshold) if lag_changes > threshold => {
+                if !self.lagging.borrow().contains(mapping)
+                    && self.notify(
+                        config,
+                        mapping,
+                        Event::Lagging,
+                        &format!(
+                            "Mapping {} is {} changes behind Perforce (threshold {}).",
+                            mapping, lag_changes, threshold
+                        ),
+                    )
+                {
+                    self.lagging.borrow_mut().insert(mapping.into());
+                }
+            }
+            _ => {
+                self.lagging.borrow_mut().remove(mapping);
+            }
+        }
+    }
+
+    pub fn failed(&self, mapping: &str, error: &str) {
+        let config = match self.config {
+            Some(config) => config,
+            None => return,
+        };
+
+        if !self.failing.borrow().contains(mapping)
+            && self.notify(
+                config,
+                mapping,
+                Event::Failing,
+                &format!("Mapping {} failed: {}", mapping, error),
+            )
+        {
+            self.failing.borrow_mut().insert(mapping.into());
+        }
+    }
+
+    /// Sends notification by all configured channels. Returns `true` if notification was
+    /// delivered by at least one channel, so state of mapping is changed only when it is known
+    /// to receivers.
+    fn notify(
+        &self,
+        config: &NotificationsConfig,
+        mapping: &str,
+        event: Event,
+        message: &str,
+    ) -> bool {
+        let key = (mapping.to_string(), event);
+        let min_interval = Duration::from_secs(config.min_interval());
+
+        if let Some(last_sent) = self.last_sent.borrow().get(&key) {
+            if last_sent.elapsed() < min_interval {
+                info!(
+                    "Notification suppressed by rate limit, mapping = {}, event = {:?}",
+                    mapping, event
+                );
+
+                return false;
+            }
+        }
+
+        let mut sent = false;
+
+        if let Some(smtp) = config.smtp() {
+            let subject = format!("perforce-sync: {} {:?}", mapping, event);
+
+            match smtp::send(
+                smtp.server(),
+                smtp.sender(),
+                smtp.recipients(),
+                &subject,
+                message,
+            ) {
+                Ok(()) => sent = true,
+                Err(err) => error!("Mail notification failed: {}", err),
+            }
+        }
+
+        if let Some(webhook) = config.webhook() {
+            let body = WebhookBody {
+                mapping,
+                event,
+                message,
+                timestamp: now_utc().rfc3339().to_string(),
+            };
+            let result = serde_json::to_string(&body)
+                .map_err(NotifyError::serialization_error)
+                .and_then(|body| webhook::send(webhook.url(), &body));
+
+            match result {
+                Ok(()) => sent = true,
+                Err(err) => error!("Webhook notification failed: {}", err),
+            }
+        }
+
+        if sent {
+            self.last_sent.borrow_mut().insert(key, Instant::now());
+        }
+
+        sent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_yaml;
+
+    use std::io::BufRead;
+    use std::io::BufReader;
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Runs webhook stand-in which answers requests with given statuses, returns events of
+    /// received notifications.
+    fn webhook(statuses: Vec<&'static str>) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut events = Vec::new();
+
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut writer = stream.try_clone().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut length = 0;
+
+                loop {
+                    let mut line = String::new();
+
+                    reader.read_line(&mut line).unwrap();
+
+                    if let Some(value) = line.strip_prefix("Content-Length: ") {
+                        length = value.trim().parse().unwrap();
+                    }
+
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                }
+
+                let mut body = vec![0; length];
+
+                reader.read_exact(&mut body).unwrap();
+
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+                events.push(body["event"].as_str().unwrap().to_string());
+                write!(writer, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+            }
+
+            events
+        });
+
+        (url, server)
+    }
+
+    fn config(url: &str, min_interval: u64) -> NotificationsConfig {
+        serde_yaml::from_str(&format!(
+            "lag_threshold: 10\nmin_interval: {}\nwebhook:\n  url: {}\n",
+            min_interval, url
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn failing_is_marked_when_sent() {
+        let (url, server) = webhook(vec!["500 Internal Server Error", "200 OK"]);
+        let config = config(&url, 3600);
+        let notifier = Notifier::new(Some(&config));
+
+        notifier.failed("main", "error");
+        assert!(!notifier.failing.borrow().contains("main"));
+
+        notifier.failed("main", "error");
+        assert!(notifier.failing.borrow().contains("main"));
+
+        notifier.failed("main", "error");
+
+        assert_eq!(server.join().unwrap(), vec!["failing", "failing"]);
+    }
+
+    #[test]
+    fn suppressed_notification_is_not_marked() {
+        let (url, server) = webhook(vec!["200 OK", "200 OK"]);
+        let config = config(&url, 3600);
+        let notifier = Notifier::new(Some(&config));
+
+        notifier.failed("main", "error");
+        notifier.succeeded("main", 0);
+        notifier.failed("main", "error");
+
+        assert!(!notifier.failing.borrow().contains("main"));
+        assert_eq!(server.join().unwrap(), vec!["failing", "recovered"]);
+    }
+
+    #[test]
+    fn lagging_is_marked_when_sent() {
+        let (url, server) = webhook(vec!["503 Service Unavailable", "200 OK"]);
+        let config = config(&url, 0);
+        let notifier = Notifier::new(Some(&config));
+
+        notifier.succeeded("main", 20);
+        assert!(!notifier.lagging.borrow().contains("main"));
+
+        notifier.succeeded("main", 20);
+        assert!(notifier.lagging.borrow().contains("main"));
+
+        notifier.succeeded("main", 20);
+        notifier.succeeded("main", 5);
+        assert!(!notifier.lagging.borrow().contains("main"));
+
+        assert_eq!(server.join().unwrap(), vec!["lagging", "lagging"]);
+    }
+}
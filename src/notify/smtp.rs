@@ -0,0 +1,207 @@
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::TcpStream;
+use std::time::Duration;
+
+use super::NotifyError;
+use super::NotifyResult;
+
+/// Sends plain text mail using SMTP server without TLS and authentication, usually local relay.
+pub fn send(
+    server: &str,
+    from: &str,
+    to: &[String],
+    subject: &str,
+    body: &str,
+) -> NotifyResult<()> {
+    info!("Sending mail, server = {}.", server);
+    let stream = TcpStream::connect(server).map_err(NotifyError::io_error)?;
+
+    stream
+        .set_read_timeout(Some(Duration::from_secs(30)))
+        .map_err(NotifyError::io_error)?;
+    stream
+        .set_write_timeout(Some(Duration::from_secs(30)))
+        .map_err(NotifyError::io_error)?;
+
+    let mut writer = stream.try_clone().map_err(NotifyError::io_error)?;
+    let mut reader = BufReader::new(stream);
+
+    expect_reply(&mut reader, "220")?;
+    command(&mut writer, &mut reader, "HELO perforce-sync", "250")?;
+    command(
+        &mut writer,
+        &mut reader,
+        &format!("MAIL FROM:<{}>", from),
+        "250",
+    )?;
+
+    for recipient in to {
+        command(
+            &mut writer,
+            &mut reader,
+            &format!("RCPT TO:<{}>", recipient),
+            "250",
+        )?;
+    }
+
+    command(&mut writer, &mut reader, "DATA", "354")?;
+
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+        from,
+        to.join(", "),
+        subject
+    );
+
+    for line in body.lines() {
+        if line.starts_with('.') {
+            message.push('.');
+        }
+
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+
+    message.push('.');
+
+    command(&mut writer, &mut reader, &message, "250")?;
+    command(&mut writer, &mut reader, "QUIT", "221")?;
+
+    debug!("Mail sent.");
+
+    Ok(())
+}
+
+fn command<R>(
+    writer: &mut TcpStream,
+    reader: &mut R,
+    line: &str,
+    expected: &str,
+) -> NotifyResult<()>
+where
+    R: BufRead,
+{
+    write!(writer, "{}\r\n", line).map_err(NotifyError::io_error)?;
+    writer.flush().map_err(NotifyError::io_error)?;
+
+    expect_reply(reader, expected)
+}
+
+fn expect_reply<R>(reader: &mut R, expected: &str) -> NotifyResult<()>
+where
+    R: BufRead,
+{
+    loop {
+        let mut line = String::new();
+
+        reader.read_line(&mut line).map_err(NotifyError::io_error)?;
+        debug!("SMTP reply {:?}.", line.trim());
+
+        if !line.starts_with(expected) {
+            return Err(NotifyError::unexpected_response(line.trim()));
+        }
+
+        // Multiline replies have dash after code, for example "250-SIZE".
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Runs SMTP server stand-in for single session, returns received lines.
+    fn server(listener: TcpListener) -> thread::JoinHandle<Vec<String>> {
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut lines = Vec::new();
+            let mut data = false;
+
+            writer.write_all(b"220 localhost\r\n").unwrap();
+
+            loop {
+                let mut line = String::new();
+
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+
+                let line = line.trim_end().to_string();
+                let reply: &[u8] = if data {
+                    if line == "." {
+                        data = false;
+                        b"250 Queued\r\n"
+                    } else {
+                        b""
+                    }
+                } else if line == "DATA" {
+                    data = true;
+                    b"354 Go ahead\r\n"
+                } else if line == "QUIT" {
+                    b"221 Bye\r\n"
+                } else if line.starts_with("HELO") {
+                    b"250-localhost\r\n250 SIZE 1000\r\n"
+                } else {
+                    b"250 OK\r\n"
+                };
+
+                writer.write_all(reply).unwrap();
+                lines.push(line);
+
+                if lines.last().map(String::as_str) == Some("QUIT") {
+                    break;
+                }
+            }
+
+            lines
+        })
+    }
+
+    #[test]
+    fn sends_mail() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = server(listener);
+
+        send(
+            &address,
+            "sync@example.com",
+            &["dev@example.com".to_string()],
+            "Subject",
+            "Line\n.dot",
+        )
+        .unwrap();
+
+        let lines = server.join().unwrap();
+
+        assert!(lines.contains(&"MAIL FROM:<sync@example.com>".to_string()));
+        assert!(lines.contains(&"RCPT TO:<dev@example.com>".to_string()));
+        assert!(lines.contains(&"Subject: Subject".to_string()));
+        assert!(lines.contains(&"..dot".to_string()));
+        assert_eq!(lines.last().map(String::as_str), Some("QUIT"));
+    }
+
+    #[test]
+    fn rejected_mail_fails() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+
+            stream.write_all(b"554 No service\r\n").unwrap();
+        });
+
+        assert!(send(&address, "a@example.com", &[], "Subject", "Body").is_err());
+
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,135 @@
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+use std::time::Duration;
+
+use super::NotifyError;
+use super::NotifyResult;
+
+/// Sends JSON body using HTTP POST request. Only plain `http://` URLs are supported, HTTPS
+/// endpoints are reached through local proxy.
+pub fn send(url: &str, body: &str) -> NotifyResult<()> {
+    info!("Sending webhook, url = {}.", url);
+    let (host, path) = parse_url(url).ok_or_else(|| NotifyError::invalid_url(url))?;
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+    let mut stream = TcpStream::connect(address).map_err(NotifyError::io_error)?;
+
+    stream
+        .set_read_timeout(Some(Duration::from_secs(30)))
+        .map_err(NotifyError::io_error)?;
+    stream
+        .set_write_timeout(Some(Duration::from_secs(30)))
+        .map_err(NotifyError::io_error)?;
+
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        body.len(),
+        body
+    ).map_err(NotifyError::io_error)?;
+    stream.flush().map_err(NotifyError::io_error)?;
+
+    let mut response = String::new();
+
+    stream
+        .read_to_string(&mut response)
+        .map_err(NotifyError::io_error)?;
+
+    let status_line = response.lines().next().unwrap_or_default();
+
+    match status_line.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => {
+            debug!("Webhook sent.");
+            Ok(())
+        }
+        _ => Err(NotifyError::unexpected_response(status_line)),
+    }
+}
+
+fn parse_url(url: &str) -> Option<(&str, &str)> {
+    let rest = url.strip_prefix("http://")?;
+
+    match rest.find('/') {
+        Some(index) => Some((&rest[..index], &rest[index..])),
+        None => Some((rest, "/")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::BufRead;
+    use std::io::BufReader;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Runs HTTP server stand-in for single request, returns request body.
+    fn server(listener: TcpListener, status: &'static str) -> thread::JoinHandle<String> {
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut length = 0;
+
+            loop {
+                let mut line = String::new();
+
+                reader.read_line(&mut line).unwrap();
+
+                if let Some(value) = line.strip_prefix("Content-Length: ") {
+                    length = value.trim().parse().unwrap();
+                }
+
+                if line.trim().is_empty() {
+                    break;
+                }
+            }
+
+            let mut body = vec![0; length];
+
+            reader.read_exact(&mut body).unwrap();
+            write!(writer, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+
+            String::from_utf8(body).unwrap()
+        })
+    }
+
+    #[test]
+    fn parses_url() {
+        assert_eq!(
+            parse_url("http://host:8080/hook"),
+            Some(("host:8080", "/hook"))
+        );
+        assert_eq!(parse_url("http://host"), Some(("host", "/")));
+        assert_eq!(parse_url("https://host/hook"), None);
+    }
+
+    #[test]
+    fn posts_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = server(listener, "204 No Content");
+
+        send(&url, "{\"event\":\"failing\"}").unwrap();
+
+        assert_eq!(server.join().unwrap(), "{\"event\":\"failing\"}");
+    }
+
+    #[test]
+    fn error_status_fails() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = server(listener, "500 Internal Server Error");
+
+        assert!(send(&url, "{}").is_err());
+
+        server.join().unwrap();
+    }
+}
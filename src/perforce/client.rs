@@ -1,4 +1,8 @@
+use encoding_rs::Encoding;
+use encoding_rs::UTF_8;
+use time::at_utc;
 use time::strptime;
+use time::Timespec;
 use time::Tm;
 
 use std::io::BufRead;
@@ -9,6 +13,9 @@ use std::io::Write;
 use std::process::Command;
 use std::process::Stdio;
 
+use metrics;
+
+use super::FileType;
 use super::PerforceError;
 use super::PerforceResult;
 
@@ -21,6 +28,8 @@ pub struct PerforceClient {
     user: String,
     password: String,
     ignore: String,
+    charset: Option<String>,
+    server_offset: Option<i32>,
     token: Option<String>,
 }
 
@@ -42,17 +51,26 @@ impl PerforceClient {
             user: user.into(),
             password: password.into(),
             ignore: ignore.into(),
+            charset: None,
+            server_offset: None,
             token: None,
         }
     }
 
+    /// Sets `P4CHARSET` for all commands. Command output is decoded using this charset.
+    pub fn set_charset(&mut self, charset: Option<&str>) {
+        self.charset = charset.map(Into::into);
+    }
+
     pub fn login(&mut self) -> PerforceResult<()> {
         info!("Perforce login.");
+        let _timer = metrics::command_timer("p4", "login");
         let mut child = Command::new(&self.command)
             .current_dir(&self.work_dir)
             .env_clear()
             .env("P4PORT", &self.port)
             .env("P4USER", &self.user)
+            .envs(self.charset.iter().map(|charset| ("P4CHARSET", charset)))
             .arg("login")
             .arg("-p")
             .stdin(Stdio::piped())
@@ -63,7 +81,8 @@ impl PerforceClient {
 
         if let Some(ref mut stdout) = child.stdout {
             debug!("Waiting for password prompt.");
-            expect_string(stdout, "Enter password: ").map_err(PerforceError::communication_error)?;
+            expect_string(stdout, "Enter password: ")
+                .map_err(PerforceError::communication_error)?;
         }
 
         if let Some(ref mut stdin) = child.stdin {
@@ -101,6 +120,7 @@ impl PerforceClient {
 
     pub fn logout(&mut self) -> PerforceResult<()> {
         info!("Perforce logout.");
+        let _timer = metrics::command_timer("p4", "logout");
         if let Some(ref token) = self.token {
             let mut child = Command::new(&self.command)
                 .current_dir(&self.work_dir)
@@ -108,6 +128,7 @@ impl PerforceClient {
                 .env("P4PORT", &self.port)
                 .env("P4PASSWD", token)
                 .env("P4USER", &self.user)
+                .envs(self.charset.iter().map(|charset| ("P4CHARSET", charset)))
                 .arg("logout")
                 .stdin(Stdio::null())
                 .stdout(Stdio::null())
@@ -130,8 +151,9 @@ impl PerforceClient {
         }
     }
 
-    pub fn sync(&mut self, directory: &str, commit: u32) -> PerforceResult<()> {
+    pub fn sync(&mut self, directories: &[String], commit: u32) -> PerforceResult<()> {
         info!("Perforce sync.");
+        let _timer = metrics::command_timer("p4", "sync");
         if let Some(ref token) = self.token {
             let mut child = Command::new(&self.command)
                 .current_dir(&self.work_dir)
@@ -140,16 +162,21 @@ impl PerforceClient {
                 .env("P4PORT", &self.port)
                 .env("P4PASSWD", token)
                 .env("P4USER", &self.user)
+                .envs(self.charset.iter().map(|charset| ("P4CHARSET", charset)))
                 .arg("sync")
                 .arg("-q")
-                .arg(format!("{}...@{}", directory, commit))
+                .args(
+                    directories
+                        .iter()
+                        .map(|directory| format!("{}...@{}", directory, commit)),
+                )
                 .stdin(Stdio::null())
                 .stdout(Stdio::null())
                 .stderr(Stdio::null())
                 .spawn()
                 .map_err(PerforceError::execution_error)?;
 
-            debug!("Execution sync {} with @{}.", directory, commit);
+            debug!("Execution sync {:?} with @{}.", directories, commit);
             let status = child.wait().map_err(PerforceError::io_error)?;
 
             if status.success() {
@@ -164,8 +191,149 @@ impl PerforceClient {
         }
     }
 
-    pub fn clean(&mut self, directory: &str) -> PerforceResult<()> {
+    /// Synchronizes only files changed by changes from `first` to `last`, other files keep their
+    /// revisions. Files of changes before `first` which are not modified again stay as they are.
+    pub fn sync_changes(
+        &mut self,
+        directories: &[String],
+        first: u32,
+        last: u32,
+    ) -> PerforceResult<()> {
+        info!("Perforce sync changes.");
+        let _timer = metrics::command_timer("p4", "sync_changes");
+        if let Some(ref token) = self.token {
+            let mut child = Command::new(&self.command)
+                .current_dir(&self.work_dir)
+                .env_clear()
+                .env("P4CLIENT", &self.client)
+                .env("P4PORT", &self.port)
+                .env("P4PASSWD", token)
+                .env("P4USER", &self.user)
+                .envs(self.charset.iter().map(|charset| ("P4CHARSET", charset)))
+                .arg("sync")
+                .arg("-q")
+                .args(
+                    directories
+                        .iter()
+                        .map(|directory| format!("{}...@{},@{}", directory, first, last)),
+                )
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(PerforceError::execution_error)?;
+
+            debug!(
+                "Execution sync {:?} with @{},@{}.",
+                directories, first, last
+            );
+            let status = child.wait().map_err(PerforceError::io_error)?;
+
+            if status.success() {
+                debug!("Sync changes complete.");
+                Ok(())
+            } else {
+                warn!("Sync changes failed.");
+                Err(PerforceError::exit_error(status.code()))
+            }
+        } else {
+            Err(PerforceError::NotLoggedIn)
+        }
+    }
+
+    pub fn unsync(&mut self, directories: &[String]) -> PerforceResult<()> {
+        info!("Perforce unsync.");
+        let _timer = metrics::command_timer("p4", "unsync");
+        if let Some(ref token) = self.token {
+            let mut child = Command::new(&self.command)
+                .current_dir(&self.work_dir)
+                .env_clear()
+                .env("P4CLIENT", &self.client)
+                .env("P4PORT", &self.port)
+                .env("P4PASSWD", token)
+                .env("P4USER", &self.user)
+                .envs(self.charset.iter().map(|charset| ("P4CHARSET", charset)))
+                .arg("sync")
+                .arg("-q")
+                .args(
+                    directories
+                        .iter()
+                        .map(|directory| format!("{}...#none", directory)),
+                )
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(PerforceError::execution_error)?;
+
+            debug!("Execution unsync {:?}.", directories);
+            let status = child.wait().map_err(PerforceError::io_error)?;
+
+            if status.success() {
+                debug!("Unsync complete.");
+                Ok(())
+            } else {
+                warn!("Unsync failed.");
+                Err(PerforceError::exit_error(status.code()))
+            }
+        } else {
+            Err(PerforceError::NotLoggedIn)
+        }
+    }
+
+    /// Removes files from workspace and from its have list, so they are not restored by
+    /// clean. File list is passed on standard input, so it is not limited by command length.
+    pub fn unsync_files(&mut self, depot_files: &[String]) -> PerforceResult<()> {
+        info!("Perforce unsync files, count = {}.", depot_files.len());
+        let _timer = metrics::command_timer("p4", "unsync_files");
+        if let Some(ref token) = self.token {
+            let mut child = Command::new(&self.command)
+                .current_dir(&self.work_dir)
+                .env_clear()
+                .env("P4CLIENT", &self.client)
+                .env("P4PORT", &self.port)
+                .env("P4PASSWD", token)
+                .env("P4USER", &self.user)
+                .envs(self.charset.iter().map(|charset| ("P4CHARSET", charset)))
+                .arg("-x")
+                .arg("-")
+                .arg("sync")
+                .arg("-q")
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(PerforceError::execution_error)?;
+
+            if let Some(mut stdin) = child.stdin.take() {
+                debug!("Sending files {:?}.", depot_files);
+                let list: String = depot_files
+                    .iter()
+                    .map(|depot_file| format!("{}#none\n", depot_file))
+                    .collect();
+
+                stdin
+                    .write_all(&self.encode(&list))
+                    .map_err(PerforceError::communication_error)?;
+            }
+
+            let status = child.wait().map_err(PerforceError::io_error)?;
+
+            if status.success() {
+                debug!("Unsync files complete.");
+                Ok(())
+            } else {
+                warn!("Unsync files failed.");
+                Err(PerforceError::exit_error(status.code()))
+            }
+        } else {
+            Err(PerforceError::NotLoggedIn)
+        }
+    }
+
+    pub fn clean(&mut self, directories: &[String]) -> PerforceResult<()> {
         info!("Perforce clean.");
+        let _timer = metrics::command_timer("p4", "clean");
         if let Some(ref token) = self.token {
             let mut child = Command::new(&self.command)
                 .current_dir(&self.work_dir)
@@ -174,16 +342,21 @@ impl PerforceClient {
                 .env("P4PORT", &self.port)
                 .env("P4PASSWD", token)
                 .env("P4USER", &self.user)
+                .envs(self.charset.iter().map(|charset| ("P4CHARSET", charset)))
                 .env("P4IGNORE", &self.ignore)
                 .arg("clean")
-                .arg(format!("{}...", directory))
+                .args(
+                    directories
+                        .iter()
+                        .map(|directory| format!("{}...", directory)),
+                )
                 .stdin(Stdio::null())
                 .stdout(Stdio::null())
                 .stderr(Stdio::null())
                 .spawn()
                 .map_err(PerforceError::execution_error)?;
 
-            debug!("Execution clean of {}.", directory);
+            debug!("Execution clean of {:?}.", directories);
             let status = child.wait().map_err(PerforceError::io_error)?;
 
             if status.success() {
@@ -198,8 +371,63 @@ impl PerforceClient {
         }
     }
 
-    pub fn changes(&mut self, directory: &str, commit: u32) -> PerforceResult<Vec<u32>> {
+    /// Restores given files to their have revisions, other files of workspace are kept as they
+    /// are. File list is passed on standard input, so it is not limited by command length.
+    pub fn clean_files(&mut self, depot_files: &[String]) -> PerforceResult<()> {
+        if depot_files.is_empty() {
+            return Ok(());
+        }
+
+        info!("Perforce clean files, count = {}.", depot_files.len());
+        let _timer = metrics::command_timer("p4", "clean_files");
+        if let Some(ref token) = self.token {
+            let mut child = Command::new(&self.command)
+                .current_dir(&self.work_dir)
+                .env_clear()
+                .env("P4CLIENT", &self.client)
+                .env("P4PORT", &self.port)
+                .env("P4PASSWD", token)
+                .env("P4USER", &self.user)
+                .envs(self.charset.iter().map(|charset| ("P4CHARSET", charset)))
+                .env("P4IGNORE", &self.ignore)
+                .arg("-x")
+                .arg("-")
+                .arg("clean")
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(PerforceError::execution_error)?;
+
+            if let Some(mut stdin) = child.stdin.take() {
+                debug!("Sending files {:?}.", depot_files);
+                let list: String = depot_files
+                    .iter()
+                    .map(|depot_file| format!("{}\n", depot_file))
+                    .collect();
+
+                stdin
+                    .write_all(&self.encode(&list))
+                    .map_err(PerforceError::communication_error)?;
+            }
+
+            let status = child.wait().map_err(PerforceError::io_error)?;
+
+            if status.success() {
+                debug!("Clean files complete.");
+                Ok(())
+            } else {
+                warn!("Clean files failed.");
+                Err(PerforceError::exit_error(status.code()))
+            }
+        } else {
+            Err(PerforceError::NotLoggedIn)
+        }
+    }
+
+    pub fn changes(&mut self, directories: &[String], commit: u32) -> PerforceResult<Vec<u32>> {
         info!("Perforce changes.");
+        let _timer = metrics::command_timer("p4", "changes");
         if let Some(ref token) = self.token {
             let mut child = Command::new(&self.command)
                 .current_dir(&self.work_dir)
@@ -208,12 +436,17 @@ impl PerforceClient {
                 .env("P4PORT", &self.port)
                 .env("P4PASSWD", token)
                 .env("P4USER", &self.user)
+                .envs(self.charset.iter().map(|charset| ("P4CHARSET", charset)))
                 .arg("-F")
                 .arg("%change%")
                 .arg("changes")
                 .arg("-e")
                 .arg(format!("{}", commit))
-                .arg(format!("{}...", directory))
+                .args(
+                    directories
+                        .iter()
+                        .map(|directory| format!("{}...", directory)),
+                )
                 .stdin(Stdio::null())
                 .stdout(Stdio::piped())
                 .stderr(Stdio::null())
@@ -222,7 +455,7 @@ impl PerforceClient {
 
             let mut result = Vec::new();
 
-            debug!("Reading changes of {}.", directory);
+            debug!("Reading changes of {:?}.", directories);
             if let Some(ref mut stdout) = child.stdout {
                 debug!("Reading all changes.");
                 let mut reader = BufReader::new(stdout);
@@ -242,6 +475,7 @@ impl PerforceClient {
             }
 
             result.sort_unstable();
+            result.dedup();
 
             let status = child.wait().map_err(PerforceError::io_error)?;
 
@@ -258,7 +492,9 @@ impl PerforceClient {
     }
 
     pub fn change(&mut self, commit: u32) -> PerforceResult<Change> {
+        let server_offset = self.server_offset()?;
         info!("Perforce change.");
+        let _timer = metrics::command_timer("p4", "change");
         if let Some(ref token) = self.token {
             let mut child = Command::new(&self.command)
                 .current_dir(&self.work_dir)
@@ -267,6 +503,7 @@ impl PerforceClient {
                 .env("P4PORT", &self.port)
                 .env("P4PASSWD", token)
                 .env("P4USER", &self.user)
+                .envs(self.charset.iter().map(|charset| ("P4CHARSET", charset)))
                 .arg("change")
                 .arg("-o")
                 .arg(format!("{}", commit))
@@ -279,8 +516,11 @@ impl PerforceClient {
             let mut change = None;
             let mut date = None;
             let mut user: Option<String> = None;
+            let mut client = String::new();
             let mut is_description = false;
             let mut description = String::new();
+            let mut is_jobs = false;
+            let mut jobs = Vec::new();
 
             debug!("Reading change of {}.", commit);
             if let Some(ref mut stdout) = child.stdout {
@@ -291,22 +531,37 @@ impl PerforceClient {
                     .read_to_end(&mut buffer)
                     .map_err(PerforceError::communication_error)?;
 
-                let buffer = String::from_utf8_lossy(&buffer);
+                let buffer = self.decode(&buffer);
 
                 for line in buffer.lines() {
                     debug!("Reading change {:?}.", line);
                     if line.starts_with("Change:") {
                         change = Some(line[8..].parse()?);
                     } else if line.starts_with("Date:") {
-                        date = Some(strptime(&line[6..], "%Y/%m/%d %H:%M:%S")
-                            .map_err(PerforceError::date_parse_error)?);
+                        date = Some(
+                            strptime(&line[6..], "%Y/%m/%d %H:%M:%S")
+                                .map_err(PerforceError::date_parse_error)?,
+                        );
                     } else if line.starts_with("User:") {
                         user = Some(line[6..].into());
+                    } else if line.starts_with("Client:") {
+                        client = line[8..].into();
                     } else if line.starts_with("Description:") {
                         is_description = true;
+                        is_jobs = false;
+                    } else if line.starts_with("Jobs:") {
+                        is_description = false;
+                        is_jobs = true;
                     } else if line.starts_with("\t") && is_description {
                         description.push_str(&line[1..]);
                         description.push_str("\n");
+                    } else if line.starts_with('\t') && is_jobs {
+                        if let Some(job) = line.split_whitespace().next() {
+                            jobs.push(job.to_string());
+                        }
+                    } else if !line.is_empty() {
+                        is_description = false;
+                        is_jobs = false;
                     }
                 }
             }
@@ -314,9 +569,18 @@ impl PerforceClient {
             let status = child.wait().map_err(PerforceError::io_error)?;
 
             if status.success() {
-                if let (Some(change), Some(date), Some(user)) = (change, date, user) {
+                if let (Some(change), Some(mut date), Some(user)) = (change, date, user) {
                     debug!("Reading change complete.");
-                    Ok(Change::new(change, date, &user, &description))
+                    date.tm_utcoff = server_offset;
+
+                    Ok(Change::new(
+                        change,
+                        date,
+                        &user,
+                        &client,
+                        &description,
+                        jobs,
+                    ))
                 } else {
                     warn!("Reading change failed.");
                     Err(PerforceError::incorrect_change(commit))
@@ -329,54 +593,1295 @@ impl PerforceClient {
             Err(PerforceError::NotLoggedIn)
         }
     }
-}
 
-#[derive(Debug)]
-pub struct Change {
-    change: u32,
-    date: Tm,
-    user: String,
-    description: String,
-}
+    /// Reads Perforce key, unset key has value `0`.
+    pub fn key(&mut self, name: &str) -> PerforceResult<String> {
+        info!("Perforce key, name = {}.", name);
+        let _timer = metrics::command_timer("p4", "key");
+        if let Some(ref token) = self.token {
+            let mut child = Command::new(&self.command)
+                .current_dir(&self.work_dir)
+                .env_clear()
+                .env("P4PORT", &self.port)
+                .env("P4PASSWD", token)
+                .env("P4USER", &self.user)
+                .envs(self.charset.iter().map(|charset| ("P4CHARSET", charset)))
+                .arg("key")
+                .arg(name)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(PerforceError::execution_error)?;
 
-impl Change {
-    fn new(change: u32, date: Tm, user: &str, description: &str) -> Change {
-        Change {
-            change,
-            date,
-            user: user.into(),
-            description: description.into(),
+            let mut buffer = String::new();
+
+            debug!("Reading key {}.", name);
+            if let Some(ref mut stdout) = child.stdout {
+                stdout
+                    .read_to_string(&mut buffer)
+                    .map_err(PerforceError::communication_error)?;
+            }
+
+            let status = child.wait().map_err(PerforceError::io_error)?;
+
+            if status.success() {
+                debug!("Key complete.");
+                Ok(buffer.trim().into())
+            } else {
+                warn!("Key failed.");
+                Err(PerforceError::exit_error(status.code()))
+            }
+        } else {
+            Err(PerforceError::NotLoggedIn)
         }
     }
 
-    pub fn change(&self) -> u32 {
-        self.change
-    }
+    /// Atomically sets Perforce key to `to` only if its current value is `from`
+    /// (`p4 key --from FROM --to TO NAME`). Returns `false` if value was not changed because it
+    /// does not match `from`, any other failure is returned as error.
+    pub fn compare_and_set_key(
+        &mut self,
+        name: &str,
+        from: &str,
+        to: &str,
+    ) -> PerforceResult<bool> {
+        info!("Perforce compare and set key, name = {}.", name);
+        let _timer = metrics::command_timer("p4", "compare_and_set_key");
+        if let Some(ref token) = self.token {
+            let mut child = Command::new(&self.command)
+                .current_dir(&self.work_dir)
+                .env_clear()
+                .env("P4PORT", &self.port)
+                .env("P4PASSWD", token)
+                .env("P4USER", &self.user)
+                .envs(self.charset.iter().map(|charset| ("P4CHARSET", charset)))
+                .arg("key")
+                .arg("--from")
+                .arg(from)
+                .arg("--to")
+                .arg(to)
+                .arg(name)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(PerforceError::execution_error)?;
 
-    pub fn date(&self) -> &Tm {
-        &self.date
-    }
+            let mut buffer = Vec::new();
 
-    pub fn user(&self) -> &str {
-        &self.user
-    }
+            debug!("Execution set key {} from {} to {}.", name, from, to);
+            if let Some(ref mut stderr) = child.stderr {
+                stderr
+                    .read_to_end(&mut buffer)
+                    .map_err(PerforceError::communication_error)?;
+            }
 
-    pub fn description(&self) -> &str {
-        &self.description
+            let status = child.wait().map_err(PerforceError::io_error)?;
+            let errors = self.decode(&buffer);
+
+            if status.success() {
+                debug!("Compare and set key complete.");
+                Ok(true)
+            } else if is_key_mismatch(&errors) {
+                debug!("Compare and set key value mismatch.");
+                Ok(false)
+            } else {
+                warn!("Compare and set key failed: {}.", errors.trim());
+                Err(PerforceError::command_error(errors.trim()))
+            }
+        } else {
+            Err(PerforceError::NotLoggedIn)
+        }
     }
-}
 
-fn expect_string(read: &mut Read, s: &str) -> IoResult<bool> {
-    let mut buffer: Vec<_> = (0..s.len()).map(|_| 0).collect();
+    pub fn files(&mut self, commit: u32) -> PerforceResult<Vec<ChangeFile>> {
+        info!("Perforce files.");
+        let _timer = metrics::command_timer("p4", "files");
+        if let Some(ref token) = self.token {
+            let mut child = Command::new(&self.command)
+                .current_dir(&self.work_dir)
+                .env_clear()
+                .env("P4CLIENT", &self.client)
+                .env("P4PORT", &self.port)
+                .env("P4PASSWD", token)
+                .env("P4USER", &self.user)
+                .envs(self.charset.iter().map(|charset| ("P4CHARSET", charset)))
+                .arg("-ztag")
+                .arg("describe")
+                .arg("-s")
+                .arg(format!("{}", commit))
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(PerforceError::execution_error)?;
 
-    read.read_exact(&mut buffer)?;
+            let mut depot_files = Vec::new();
+            let mut actions = Vec::new();
+            let mut file_types = Vec::new();
 
-    Ok(buffer == s.as_bytes())
-}
+            debug!("Reading files of {}.", commit);
+            if let Some(ref mut stdout) = child.stdout {
+                debug!("Reading all files.");
+                let mut buffer = Vec::with_capacity(1024);
 
-fn send_string(write: &mut Write, s: &str) -> IoResult<()> {
-    write.write(s.as_bytes())?;
-    write.write(b"\n")?;
+                stdout
+                    .read_to_end(&mut buffer)
+                    .map_err(PerforceError::communication_error)?;
 
-    Ok(())
+                for line in self.decode(&buffer).lines() {
+                    debug!("Reading file {:?}.", line);
+
+                    if let Some((key, value)) = parse_tag(line) {
+                        if key.starts_with("depotFile") {
+                            depot_files.push(value.to_string());
+                        } else if key.starts_with("action") {
+                            actions.push(value.to_string());
+                        } else if key.starts_with("type") {
+                            file_types.push(FileType::parse(value));
+                        }
+                    }
+                }
+            }
+
+            let status = child.wait().map_err(PerforceError::io_error)?;
+
+            if status.success() {
+                if depot_files.len() == actions.len() && depot_files.len() == file_types.len() {
+                    debug!("Files complete.");
+                    Ok(depot_files
+                        .into_iter()
+                        .zip(actions)
+                        .zip(file_types)
+                        .map(|((depot_file, action), file_type)| {
+                            ChangeFile::new(&depot_file, &action, file_type)
+                        })
+                        .collect())
+                } else {
+                    warn!("Reading files failed.");
+                    Err(PerforceError::incorrect_change(commit))
+                }
+            } else {
+                warn!("Files failed.");
+                Err(PerforceError::exit_error(status.code()))
+            }
+        } else {
+            Err(PerforceError::NotLoggedIn)
+        }
+    }
+
+    /// Returns digests and sizes of files at change, as reported by `p4 fstat -Ol`. Deleted
+    /// files are listed too, so change which deleted file is known.
+    pub fn digests(
+        &mut self,
+        directories: &[String],
+        commit: u32,
+    ) -> PerforceResult<Vec<FileDigest>> {
+        info!("Perforce digests.");
+        let _timer = metrics::command_timer("p4", "digests");
+        if let Some(ref token) = self.token {
+            let mut child = Command::new(&self.command)
+                .current_dir(&self.work_dir)
+                .env_clear()
+                .env("P4CLIENT", &self.client)
+                .env("P4PORT", &self.port)
+                .env("P4PASSWD", token)
+                .env("P4USER", &self.user)
+                .envs(self.charset.iter().map(|charset| ("P4CHARSET", charset)))
+                .arg("-ztag")
+                .arg("fstat")
+                .arg("-Ol")
+                .arg("-T")
+                .arg("depotFile,headAction,headChange,headType,digest,fileSize")
+                .args(
+                    directories
+                        .iter()
+                        .map(|directory| format!("{}...@{}", directory, commit)),
+                )
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(PerforceError::execution_error)?;
+
+            let mut result = Vec::new();
+
+            debug!("Reading digests of {:?} at {}.", directories, commit);
+            if let Some(ref mut stdout) = child.stdout {
+                let mut buffer = Vec::with_capacity(1024);
+
+                stdout
+                    .read_to_end(&mut buffer)
+                    .map_err(PerforceError::communication_error)?;
+
+                let mut current: Option<FileDigest> = None;
+
+                for line in self.decode(&buffer).lines() {
+                    debug!("Reading digest {:?}.", line);
+
+                    if let Some((key, value)) = parse_tag(line) {
+                        match key {
+                            "depotFile" => {
+                                result.extend(current.take());
+                                current = Some(FileDigest::new(value));
+                            }
+                            _ => {
+                                if let Some(ref mut file) = current {
+                                    match key {
+                                        "headAction" => file.action = value.into(),
+                                        "headChange" => file.change = value.parse()?,
+                                        "headType" => file.file_type = FileType::parse(value),
+                                        "digest" => file.digest = value.into(),
+                                        "fileSize" => file.size = value.parse()?,
+                                        _ => {}
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+
+                result.extend(current);
+            }
+
+            let status = child.wait().map_err(PerforceError::io_error)?;
+
+            if status.success() {
+                debug!("Digests complete.");
+                Ok(result)
+            } else {
+                warn!("Digests failed.");
+                Err(PerforceError::exit_error(status.code()))
+            }
+        } else {
+            Err(PerforceError::NotLoggedIn)
+        }
+    }
+
+    /// Returns submitted changes of directories in range with full descriptions. Dates are in UTC,
+    /// `change` gives date in server time zone.
+    pub fn describe_changes(
+        &mut self,
+        directories: &[String],
+        first: u32,
+        last: u32,
+    ) -> PerforceResult<Vec<Change>> {
+        info!(
+            "Perforce describe changes, first = {}, last = {}.",
+            first, last
+        );
+        let _timer = metrics::command_timer("p4", "describe_changes");
+        if let Some(ref token) = self.token {
+            let mut child = Command::new(&self.command)
+                .current_dir(&self.work_dir)
+                .env_clear()
+                .env("P4CLIENT", &self.client)
+                .env("P4PORT", &self.port)
+                .env("P4PASSWD", token)
+                .env("P4USER", &self.user)
+                .envs(self.charset.iter().map(|charset| ("P4CHARSET", charset)))
+                .arg("-ztag")
+                .arg("changes")
+                .arg("-l")
+                .arg("-s")
+                .arg("submitted")
+                .args(
+                    directories
+                        .iter()
+                        .map(|directory| format!("{}...@{},@{}", directory, first, last)),
+                )
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(PerforceError::execution_error)?;
+
+            let mut result = Vec::new();
+
+            debug!(
+                "Reading changes of {:?} from {} to {}.",
+                directories, first, last
+            );
+            if let Some(ref mut stdout) = child.stdout {
+                let mut buffer = Vec::with_capacity(1024);
+
+                stdout
+                    .read_to_end(&mut buffer)
+                    .map_err(PerforceError::communication_error)?;
+
+                result = parse_changes(&self.decode(&buffer))?;
+            }
+
+            let status = child.wait().map_err(PerforceError::io_error)?;
+
+            if status.success() {
+                debug!("Describe changes complete.");
+                Ok(result)
+            } else {
+                warn!("Describe changes failed.");
+                Err(PerforceError::exit_error(status.code()))
+            }
+        } else {
+            Err(PerforceError::NotLoggedIn)
+        }
+    }
+
+    /// Returns files existing at change.
+    pub fn head_files(
+        &mut self,
+        directories: &[String],
+        commit: u32,
+    ) -> PerforceResult<Vec<ChangeFile>> {
+        let mut files = self.head_revisions(directories, commit)?;
+        files.retain(|file| !file.is_deleted());
+
+        Ok(files)
+    }
+
+    /// Returns head revisions of files at change, deleted files are included.
+    pub fn head_revisions(
+        &mut self,
+        directories: &[String],
+        commit: u32,
+    ) -> PerforceResult<Vec<ChangeFile>> {
+        info!("Perforce head revisions.");
+        let _timer = metrics::command_timer("p4", "head_revisions");
+        if let Some(ref token) = self.token {
+            let mut child = Command::new(&self.command)
+                .current_dir(&self.work_dir)
+                .env_clear()
+                .env("P4CLIENT", &self.client)
+                .env("P4PORT", &self.port)
+                .env("P4PASSWD", token)
+                .env("P4USER", &self.user)
+                .envs(self.charset.iter().map(|charset| ("P4CHARSET", charset)))
+                .arg("-ztag")
+                .arg("fstat")
+                .arg("-T")
+                .arg("depotFile,headAction,headType")
+                .args(
+                    directories
+                        .iter()
+                        .map(|directory| format!("{}...@{}", directory, commit)),
+                )
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(PerforceError::execution_error)?;
+
+            let mut result = Vec::new();
+
+            debug!("Reading files of {:?} at {}.", directories, commit);
+            if let Some(ref mut stdout) = child.stdout {
+                let mut buffer = Vec::with_capacity(1024);
+
+                stdout
+                    .read_to_end(&mut buffer)
+                    .map_err(PerforceError::communication_error)?;
+
+                let mut current: Option<ChangeFile> = None;
+
+                for line in self.decode(&buffer).lines() {
+                    debug!("Reading head file {:?}.", line);
+
+                    if let Some((key, value)) = parse_tag(line) {
+                        match key {
+                            "depotFile" => {
+                                result.extend(current.take());
+                                current = Some(ChangeFile::new(value, "", FileType::parse("")));
+                            }
+                            "headAction" => {
+                                if let Some(ref mut file) = current {
+                                    file.action = value.into();
+                                }
+                            }
+                            "headType" => {
+                                if let Some(ref mut file) = current {
+                                    file.file_type = FileType::parse(value);
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+
+                result.extend(current);
+            }
+
+            let status = child.wait().map_err(PerforceError::io_error)?;
+
+            if status.success() {
+                debug!("Head revisions complete.");
+                Ok(result)
+            } else {
+                warn!("Head revisions failed.");
+                Err(PerforceError::exit_error(status.code()))
+            }
+        } else {
+            Err(PerforceError::NotLoggedIn)
+        }
+    }
+
+    /// Returns server time zone offset in seconds east of UTC. Offset is read from `p4 info`
+    /// once and cached.
+    pub fn server_offset(&mut self) -> PerforceResult<i32> {
+        if let Some(offset) = self.server_offset {
+            return Ok(offset);
+        }
+
+        info!("Perforce info.");
+        let _timer = metrics::command_timer("p4", "info");
+        if let Some(ref token) = self.token {
+            let mut child = Command::new(&self.command)
+                .current_dir(&self.work_dir)
+                .env_clear()
+                .env("P4PORT", &self.port)
+                .env("P4PASSWD", token)
+                .env("P4USER", &self.user)
+                .envs(self.charset.iter().map(|charset| ("P4CHARSET", charset)))
+                .arg("info")
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(PerforceError::execution_error)?;
+
+            let mut offset = None;
+
+            debug!("Reading server info.");
+            if let Some(ref mut stdout) = child.stdout {
+                let mut buffer = Vec::new();
+
+                stdout
+                    .read_to_end(&mut buffer)
+                    .map_err(PerforceError::communication_error)?;
+
+                // Output contains server and client names in server charset.
+                for line in self.decode(&buffer).lines() {
+                    debug!("Reading info {:?}.", line);
+
+                    if let Some(date) = line.strip_prefix("Server date: ") {
+                        offset = date.split_whitespace().nth(2).and_then(parse_offset);
+                    }
+                }
+            }
+
+            let status = child.wait().map_err(PerforceError::io_error)?;
+
+            if status.success() {
+                match offset {
+                    Some(offset) => {
+                        debug!("Server offset = {}.", offset);
+                        self.server_offset = Some(offset);
+
+                        Ok(offset)
+                    }
+                    None => {
+                        warn!("Server date not found.");
+                        Err(PerforceError::ParsingServerDateFailed)
+                    }
+                }
+            } else {
+                warn!("Info failed.");
+                Err(PerforceError::exit_error(status.code()))
+            }
+        } else {
+            Err(PerforceError::NotLoggedIn)
+        }
+    }
+
+    /// Reads client workspace spec. Spec of missing workspace is generated by server and marked
+    /// as not existing.
+    pub fn client_spec(&mut self) -> PerforceResult<ClientSpec> {
+        info!("Perforce client spec.");
+        let _timer = metrics::command_timer("p4", "client_spec");
+        if let Some(ref token) = self.token {
+            let mut child = Command::new(&self.command)
+                .current_dir(&self.work_dir)
+                .env_clear()
+                .env("P4CLIENT", &self.client)
+                .env("P4PORT", &self.port)
+                .env("P4PASSWD", token)
+                .env("P4USER", &self.user)
+                .envs(self.charset.iter().map(|charset| ("P4CHARSET", charset)))
+                .arg("-ztag")
+                .arg("client")
+                .arg("-o")
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(PerforceError::execution_error)?;
+
+            let mut spec = ClientSpec::default();
+
+            debug!("Reading client spec {}.", self.client);
+            if let Some(ref mut stdout) = child.stdout {
+                let mut buffer = Vec::with_capacity(1024);
+
+                stdout
+                    .read_to_end(&mut buffer)
+                    .map_err(PerforceError::communication_error)?;
+
+                for line in self.decode(&buffer).lines() {
+                    debug!("Reading client spec {:?}.", line);
+
+                    if let Some((key, value)) = parse_tag(line) {
+                        match key {
+                            "Root" => spec.root = value.into(),
+                            "Options" => spec.options = value.into(),
+                            "LineEnd" => spec.line_end = value.into(),
+                            "Update" | "Access" => spec.exists = true,
+                            _ if key.starts_with("View") => spec.view.push(value.into()),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+
+            let status = child.wait().map_err(PerforceError::io_error)?;
+
+            if status.success() {
+                debug!("Client spec complete.");
+                Ok(spec)
+            } else {
+                warn!("Client spec failed.");
+                Err(PerforceError::exit_error(status.code()))
+            }
+        } else {
+            Err(PerforceError::NotLoggedIn)
+        }
+    }
+
+    /// Creates or updates client workspace. Current spec form is read with `p4 client -o`, only
+    /// `Root`, `Options`, `LineEnd` and `View` fields are replaced, other fields are kept.
+    pub fn set_client_spec(&mut self, spec: &ClientSpec) -> PerforceResult<()> {
+        let form = update_client_form(&self.client_form()?, spec);
+
+        info!("Perforce set client spec.");
+        let _timer = metrics::command_timer("p4", "set_client_spec");
+        if let Some(ref token) = self.token {
+            let mut child = Command::new(&self.command)
+                .current_dir(&self.work_dir)
+                .env_clear()
+                .env("P4CLIENT", &self.client)
+                .env("P4PORT", &self.port)
+                .env("P4PASSWD", token)
+                .env("P4USER", &self.user)
+                .envs(self.charset.iter().map(|charset| ("P4CHARSET", charset)))
+                .arg("client")
+                .arg("-i")
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(PerforceError::execution_error)?;
+
+            if let Some(mut stdin) = child.stdin.take() {
+                debug!("Sending client spec {:?}.", form);
+                stdin
+                    .write_all(&self.encode(&form))
+                    .map_err(PerforceError::communication_error)?;
+            }
+
+            let status = child.wait().map_err(PerforceError::io_error)?;
+
+            if status.success() {
+                debug!("Set client spec complete.");
+                Ok(())
+            } else {
+                warn!("Set client spec failed.");
+                Err(PerforceError::exit_error(status.code()))
+            }
+        } else {
+            Err(PerforceError::NotLoggedIn)
+        }
+    }
+
+    /// Reads client workspace spec form, form of missing workspace is generated by server.
+    fn client_form(&mut self) -> PerforceResult<String> {
+        info!("Perforce client form.");
+        let _timer = metrics::command_timer("p4", "client_form");
+        if let Some(ref token) = self.token {
+            let mut child = Command::new(&self.command)
+                .current_dir(&self.work_dir)
+                .env_clear()
+                .env("P4CLIENT", &self.client)
+                .env("P4PORT", &self.port)
+                .env("P4PASSWD", token)
+                .env("P4USER", &self.user)
+                .envs(self.charset.iter().map(|charset| ("P4CHARSET", charset)))
+                .arg("client")
+                .arg("-o")
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(PerforceError::execution_error)?;
+
+            let mut buffer = Vec::with_capacity(1024);
+
+            debug!("Reading client form {}.", self.client);
+            if let Some(ref mut stdout) = child.stdout {
+                stdout
+                    .read_to_end(&mut buffer)
+                    .map_err(PerforceError::communication_error)?;
+            }
+
+            let status = child.wait().map_err(PerforceError::io_error)?;
+
+            if status.success() {
+                debug!("Client form complete.");
+                Ok(self.decode(&buffer))
+            } else {
+                warn!("Client form failed.");
+                Err(PerforceError::exit_error(status.code()))
+            }
+        } else {
+            Err(PerforceError::NotLoggedIn)
+        }
+    }
+
+    pub fn users(&mut self) -> PerforceResult<Vec<User>> {
+        info!("Perforce users.");
+        let _timer = metrics::command_timer("p4", "users");
+        if let Some(ref token) = self.token {
+            let mut child = Command::new(&self.command)
+                .current_dir(&self.work_dir)
+                .env_clear()
+                .env("P4PORT", &self.port)
+                .env("P4PASSWD", token)
+                .env("P4USER", &self.user)
+                .envs(self.charset.iter().map(|charset| ("P4CHARSET", charset)))
+                .arg("-ztag")
+                .arg("users")
+                .arg("-a")
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(PerforceError::execution_error)?;
+
+            let mut result: Vec<User> = Vec::new();
+
+            debug!("Reading users.");
+            if let Some(ref mut stdout) = child.stdout {
+                let mut buffer = Vec::with_capacity(1024);
+
+                stdout
+                    .read_to_end(&mut buffer)
+                    .map_err(PerforceError::communication_error)?;
+
+                for line in self.decode(&buffer).lines() {
+                    debug!("Reading user {:?}.", line);
+
+                    match (parse_tag(line), result.last_mut()) {
+                        (Some(("User", value)), _) => result.push(User::new(value)),
+                        (Some(("Email", value)), Some(user)) => user.email = value.into(),
+                        (Some(("FullName", value)), Some(user)) => user.full_name = value.into(),
+                        _ => {}
+                    }
+                }
+            }
+
+            let status = child.wait().map_err(PerforceError::io_error)?;
+
+            if status.success() {
+                debug!("Users complete.");
+                Ok(result)
+            } else {
+                warn!("Users failed.");
+                Err(PerforceError::exit_error(status.code()))
+            }
+        } else {
+            Err(PerforceError::NotLoggedIn)
+        }
+    }
+
+    fn decode(&self, buffer: &[u8]) -> String {
+        let encoding = self.encoding();
+        let (text, had_errors) = encoding.decode_without_bom_handling(buffer);
+
+        if had_errors {
+            warn!("Output is not valid {}.", encoding.name());
+        }
+
+        text.into_owned()
+    }
+
+    /// Encodes command input in the same charset as output is decoded from.
+    fn encode(&self, text: &str) -> Vec<u8> {
+        let encoding = self.encoding();
+        let (bytes, _, had_errors) = encoding.encode(text);
+
+        if had_errors {
+            warn!("Input can not be encoded in {}.", encoding.name());
+        }
+
+        bytes.into_owned()
+    }
+
+    fn encoding(&self) -> &'static Encoding {
+        self.charset
+            .as_ref()
+            .and_then(|charset| charset_encoding(charset))
+            .unwrap_or(UTF_8)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Change {
+    change: u32,
+    date: Tm,
+    user: String,
+    client: String,
+    description: String,
+    jobs: Vec<String>,
+}
+
+impl Change {
+    pub fn new(
+        change: u32,
+        date: Tm,
+        user: &str,
+        client: &str,
+        description: &str,
+        jobs: Vec<String>,
+    ) -> Change {
+        Change {
+            change,
+            date,
+            user: user.into(),
+            client: client.into(),
+            description: description.into(),
+            jobs,
+        }
+    }
+
+    pub fn change(&self) -> u32 {
+        self.change
+    }
+
+    /// Submit date in server time zone, `tm_utcoff` contains server offset.
+    pub fn date(&self) -> &Tm {
+        &self.date
+    }
+
+    /// Submit time in seconds since Unix epoch.
+    pub fn timestamp(&self) -> i64 {
+        let utc = Tm {
+            tm_utcoff: 0,
+            ..self.date
+        };
+
+        utc.to_timespec().sec - i64::from(self.date.tm_utcoff)
+    }
+
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+
+    pub fn client(&self) -> &str {
+        &self.client
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn jobs(&self) -> &[String] {
+        &self.jobs
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChangeFile {
+    depot_file: String,
+    action: String,
+    file_type: FileType,
+}
+
+impl ChangeFile {
+    fn new(depot_file: &str, action: &str, file_type: FileType) -> ChangeFile {
+        ChangeFile {
+            depot_file: depot_file.into(),
+            action: action.into(),
+            file_type,
+        }
+    }
+
+    pub fn depot_file(&self) -> &str {
+        &self.depot_file
+    }
+
+    pub fn action(&self) -> &str {
+        &self.action
+    }
+
+    pub fn file_type(&self) -> &FileType {
+        &self.file_type
+    }
+
+    /// Checks whether file is removed from workspace by change.
+    pub fn is_deleted(&self) -> bool {
+        self.action.ends_with("delete") || self.action == "purge" || self.action == "archive"
+    }
+}
+
+/// Client workspace spec fields managed by tool.
+#[derive(Debug, Default)]
+pub struct ClientSpec {
+    root: String,
+    options: String,
+    line_end: String,
+    view: Vec<String>,
+    exists: bool,
+}
+
+impl ClientSpec {
+    pub fn new(root: &str, options: &str, line_end: &str, view: Vec<String>) -> ClientSpec {
+        ClientSpec {
+            root: root.into(),
+            options: options.into(),
+            line_end: line_end.into(),
+            view,
+            exists: true,
+        }
+    }
+
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    pub fn options(&self) -> &str {
+        &self.options
+    }
+
+    pub fn line_end(&self) -> &str {
+        &self.line_end
+    }
+
+    pub fn view(&self) -> &[String] {
+        &self.view
+    }
+
+    pub fn exists(&self) -> bool {
+        self.exists
+    }
+}
+
+#[derive(Debug)]
+pub struct FileDigest {
+    depot_file: String,
+    action: String,
+    change: u32,
+    file_type: FileType,
+    digest: String,
+    size: u64,
+}
+
+impl FileDigest {
+    fn new(depot_file: &str) -> FileDigest {
+        FileDigest {
+            depot_file: depot_file.into(),
+            action: String::new(),
+            change: 0,
+            file_type: FileType::parse("text"),
+            digest: String::new(),
+            size: 0,
+        }
+    }
+
+    pub fn depot_file(&self) -> &str {
+        &self.depot_file
+    }
+
+    /// Change of the file revision.
+    pub fn change(&self) -> u32 {
+        self.change
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.action.ends_with("delete") || self.action == "purge" || self.action == "archive"
+    }
+
+    pub fn file_type(&self) -> &FileType {
+        &self.file_type
+    }
+
+    /// Uppercase hexadecimal MD5 digest of file content.
+    pub fn digest(&self) -> &str {
+        &self.digest
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+#[derive(Debug)]
+pub struct User {
+    user: String,
+    email: String,
+    full_name: String,
+}
+
+impl User {
+    fn new(user: &str) -> User {
+        User {
+            user: user.into(),
+            email: String::new(),
+            full_name: String::new(),
+        }
+    }
+
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    pub fn full_name(&self) -> &str {
+        &self.full_name
+    }
+}
+
+/// Parses time zone offset in format `+HHMM` to seconds east of UTC.
+fn parse_offset(offset: &str) -> Option<i32> {
+    let (sign, digits) = if let Some(digits) = offset.strip_prefix('+') {
+        (1, digits)
+    } else {
+        (-1, offset.strip_prefix('-')?)
+    };
+
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Returns encoding for Perforce charset name.
+fn charset_encoding(charset: &str) -> Option<&'static Encoding> {
+    let label = match charset {
+        "utf8" | "utf8-bom" | "utf8unchecked" | "utf8unchecked-bom" => "utf-8",
+        "winansi" => "windows-1252",
+        "iso8859-1" => "iso-8859-1",
+        "iso8859-5" => "iso-8859-5",
+        "iso8859-7" => "iso-8859-7",
+        "iso8859-15" => "iso-8859-15",
+        "shiftjis" => "shift_jis",
+        "eucjp" => "euc-jp",
+        "eucjp-win" => "euc-jp",
+        "cp936" => "gbk",
+        "cp949" => "euc-kr",
+        "cp950" => "big5",
+        "cp866" => "ibm866",
+        "cp1251" => "windows-1251",
+        "cp1253" => "windows-1253",
+        "koi8-r" => "koi8-r",
+        "macosroman" => "macintosh",
+        other => other,
+    };
+
+    Encoding::for_label(label.as_bytes())
+}
+
+/// Replaces managed fields of client spec form, other fields and comments are kept. Missing
+/// managed fields are appended.
+fn update_client_form(form: &str, spec: &ClientSpec) -> String {
+    let view: String = spec
+        .view
+        .iter()
+        .map(|line| format!("\n\t{}", line))
+        .collect();
+    let mut fields = vec![
+        ("Root", format!("Root:\t{}", spec.root)),
+        ("Options", format!("Options:\t{}", spec.options)),
+        ("LineEnd", format!("LineEnd:\t{}", spec.line_end)),
+        ("View", format!("View:{}", view)),
+    ];
+    let mut result = String::new();
+    let mut skipping = false;
+
+    for line in form.lines() {
+        if line.starts_with('\t') || line.starts_with(' ') {
+            if !skipping {
+                result.push_str(line);
+                result.push('\n');
+            }
+
+            continue;
+        }
+
+        let name = line.split(':').next().unwrap_or_default();
+
+        skipping = false;
+
+        if let Some(index) = fields.iter().position(|&(field, _)| field == name) {
+            let (_, value) = fields.remove(index);
+
+            result.push_str(&value);
+            result.push('\n');
+            skipping = true;
+        } else {
+            result.push_str(line);
+            result.push('\n');
+        }
+    }
+
+    for (_, value) in fields {
+        result.push('\n');
+        result.push_str(&value);
+        result.push('\n');
+    }
+
+    result
+}
+
+/// Parses tagged output of `p4 changes -l`, description continues on lines without tag. Changes
+/// are sorted and deduplicated, as several directories may list the same change.
+fn parse_changes(output: &str) -> PerforceResult<Vec<Change>> {
+    let mut result = Vec::new();
+    let mut current: Option<Change> = None;
+    let mut in_description = false;
+
+    for line in output.lines() {
+        match parse_tag(line) {
+            Some((key, value)) => {
+                in_description = false;
+
+                match key {
+                    "change" => {
+                        result.extend(current.take());
+                        current = Some(Change::new(
+                            value.parse()?,
+                            at_utc(Timespec::new(0, 0)),
+                            "",
+                            "",
+                            "",
+                            Vec::new(),
+                        ));
+                    }
+                    "time" => {
+                        if let Some(ref mut change) = current {
+                            change.date = at_utc(Timespec::new(value.parse()?, 0));
+                        }
+                    }
+                    "user" => {
+                        if let Some(ref mut change) = current {
+                            change.user = value.into();
+                        }
+                    }
+                    "client" => {
+                        if let Some(ref mut change) = current {
+                            change.client = value.into();
+                        }
+                    }
+                    "desc" => {
+                        if let Some(ref mut change) = current {
+                            change.description = value.into();
+                            in_description = true;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            None if in_description => {
+                if let Some(ref mut change) = current {
+                    change.description.push('\n');
+                    change.description.push_str(line);
+                }
+            }
+            None => {}
+        }
+    }
+
+    result.extend(current);
+
+    for change in &mut result {
+        let length = change.description.trim_end().len();
+        change.description.truncate(length);
+    }
+
+    result.sort_unstable_by_key(Change::change);
+    result.dedup_by_key(|change| change.change);
+
+    Ok(result)
+}
+
+/// Checks whether `p4 key --from` failed because current value differs from expected one,
+/// server reports it as `Key 'NAME' value does not match 'VALUE'.`
+fn is_key_mismatch(errors: &str) -> bool {
+    errors.contains("value does not match")
+}
+
+fn parse_tag(line: &str) -> Option<(&str, &str)> {
+    line.strip_prefix("... ").map(|tag| match tag.find(' ') {
+        Some(index) => (&tag[..index], &tag[index + 1..]),
+        None => (tag, ""),
+    })
+}
+
+fn expect_string(read: &mut Read, s: &str) -> IoResult<bool> {
+    let mut buffer: Vec<_> = (0..s.len()).map(|_| 0).collect();
+
+    read.read_exact(&mut buffer)?;
+
+    Ok(buffer == s.as_bytes())
+}
+
+fn send_string(write: &mut Write, s: &str) -> IoResult<()> {
+    write.write(s.as_bytes())?;
+    write.write(b"\n")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_changes() {
+        let output = "... change 12
+... time 1500000100
+... user bob
+... client bob-ws
+... status submitted
+... desc Update parser
+
+Second line.
+
+... change 10
+... time 1500000000
+... user alice
+... client alice-ws
+... status submitted
+... desc Fix build
+
+... change 12
+... time 1500000100
+... user bob
+... client bob-ws
+... status submitted
+... desc Update parser
+
+Second line.
+
+";
+        let changes = parse_changes(output).unwrap();
+
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].change(), 10);
+        assert_eq!(changes[0].user(), "alice");
+        assert_eq!(changes[0].description(), "Fix build");
+        assert_eq!(changes[1].change(), 12);
+        assert_eq!(changes[1].timestamp(), 1_500_000_100);
+        assert_eq!(changes[1].description(), "Update parser\n\nSecond line.");
+    }
+
+    #[test]
+    fn updates_managed_client_fields() {
+        let form = "# A Perforce Client Specification.
+#
+#  Root:        The base directory of the client workspace.
+
+Client:\tsync
+
+Owner:\tbuild
+
+Host:\tbuild-host
+
+Description:
+\tShared workspace.
+
+Root:\t/old
+
+AltRoots:
+\t/alt
+
+Options:\tnoallwrite noclobber nocompress unlocked nomodtime normdir
+
+SubmitOptions:\trevertunchanged
+
+View:
+\t//depot/old/... //sync/old/...
+";
+        let spec = ClientSpec::new(
+            "/work",
+            "noallwrite clobber nocompress unlocked nomodtime rmdir",
+            "unix",
+            vec!["//depot/project/... //sync/...".into()],
+        );
+        let updated = update_client_form(form, &spec);
+
+        assert!(updated.contains("#  Root:        The base directory"));
+        assert!(updated.contains("Owner:\tbuild\n"));
+        assert!(updated.contains("Host:\tbuild-host\n"));
+        assert!(updated.contains("Description:\n\tShared workspace.\n"));
+        assert!(updated.contains("AltRoots:\n\t/alt\n"));
+        assert!(updated.contains("SubmitOptions:\trevertunchanged\n"));
+        assert!(updated.contains("Root:\t/work\n"));
+        assert!(
+            updated.contains("Options:\tnoallwrite clobber nocompress unlocked nomodtime rmdir\n")
+        );
+        assert!(updated.contains("LineEnd:\tunix\n"));
+        assert!(updated.contains("View:\n\t//depot/project/... //sync/...\n"));
+        assert!(!updated.contains("/old"));
+    }
+
+    #[test]
+    fn parses_tags() {
+        assert_eq!(
+            parse_tag("... depotFile //depot/a b"),
+            Some(("depotFile", "//depot/a b"))
+        );
+        assert_eq!(parse_tag("... isMapped"), Some(("isMapped", "")));
+        assert_eq!(parse_tag("depotFile"), None);
+    }
+
+    #[test]
+    fn detects_key_mismatch() {
+        assert!(is_key_mismatch(
+            "Key 'perforce-sync-lease' value does not match 'host:1 100'.\n"
+        ));
+        assert!(!is_key_mismatch(
+            "You don't have permission for this operation.\n"
+        ));
+        assert!(!is_key_mismatch(
+            "Perforce client error:\n\tConnect to server failed; check $P4PORT.\n"
+        ));
+        assert!(!is_key_mismatch(
+            "Protections table doesn't match any entry for key 'perforce-sync-lease'.\n"
+        ));
+    }
+
+    #[test]
+    fn parses_server_offset() {
+        assert_eq!(parse_offset("-0800"), Some(-8 * 3600));
+        assert_eq!(parse_offset("+0530"), Some(5 * 3600 + 30 * 60));
+        assert_eq!(parse_offset("PST"), None);
+        assert_eq!(parse_offset("+08"), None);
+    }
+
+    #[test]
+    fn encodes_input_in_charset() {
+        let mut client = PerforceClient::new("p4", "/work", "ws", "perforce:1666", "u", "", "");
+        let path = "//depot/\u{0434}\u{043e}\u{043a}.txt\n";
+
+        assert_eq!(client.encode(path), path.as_bytes());
+
+        client.set_charset(Some("cp1251"));
+
+        assert_eq!(client.encode(path), b"//depot/\xe4\xee\xea.txt\n");
+        assert_eq!(client.decode(&client.encode(path)), path);
+    }
 }
@@ -13,11 +13,14 @@ pub enum PerforceError {
     ExecutionError { message: String },
     CommunicationError { message: String },
     ExitError { exit_code: Option<i32> },
+    CommandError { message: String },
     IncorrectChange { commit: u32 },
     LoginFailed,
     NotLoggedIn,
     ParsingChangeFailed,
+    ParsingServerDateFailed,
     DateParseError { message: String },
+    LocalPathOutsideRoot { path: String },
 }
 
 impl PerforceError {
@@ -43,6 +46,12 @@ impl PerforceError {
         PerforceError::ExitError { exit_code }
     }
 
+    pub fn command_error(message: &str) -> PerforceError {
+        PerforceError::CommandError {
+            message: message.into(),
+        }
+    }
+
     pub fn incorrect_change(commit: u32) -> PerforceError {
         PerforceError::IncorrectChange { commit }
     }
@@ -52,6 +61,10 @@ impl PerforceError {
             message: error.description().into(),
         }
     }
+
+    pub fn local_path_outside_root(path: &str) -> PerforceError {
+        PerforceError::LocalPathOutsideRoot { path: path.into() }
+    }
 }
 
 impl From<ParseIntError> for PerforceError {
@@ -67,11 +80,16 @@ impl Display for PerforceError {
             PerforceError::ExecutionError { .. } => write!(f, "Execution error"),
             PerforceError::CommunicationError { .. } => write!(f, "Communication error"),
             PerforceError::ExitError { .. } => write!(f, "Exit error"),
+            PerforceError::CommandError { message } => write!(f, "Command failed: {}", message),
             PerforceError::IncorrectChange { .. } => write!(f, "Incorrect change"),
             PerforceError::LoginFailed => write!(f, "Login failed"),
             PerforceError::NotLoggedIn => write!(f, "Not logged in"),
             PerforceError::ParsingChangeFailed => write!(f, "Parsing change failed"),
+            PerforceError::ParsingServerDateFailed => write!(f, "Parsing server date failed"),
             PerforceError::DateParseError { .. } => write!(f, "Time parse error"),
+            PerforceError::LocalPathOutsideRoot { path } => {
+                write!(f, "Local path {} is outside of workspace root", path)
+            }
         }
     }
 }
@@ -83,11 +101,14 @@ impl Error for PerforceError {
             PerforceError::ExecutionError { .. } => "Execution error",
             PerforceError::CommunicationError { .. } => "Communication error",
             PerforceError::ExitError { .. } => "Exit error",
+            PerforceError::CommandError { .. } => "Command failed",
             PerforceError::IncorrectChange { .. } => "Incorrect change",
             PerforceError::LoginFailed => "Login failed",
             PerforceError::NotLoggedIn => "Not logged in",
             PerforceError::ParsingChangeFailed => "Parsing change failed",
+            PerforceError::ParsingServerDateFailed => "Parsing server date failed",
             PerforceError::DateParseError { .. } => "Time parse error",
+            PerforceError::LocalPathOutsideRoot { .. } => "Local path outside root",
         }
     }
 }
@@ -0,0 +1,103 @@
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
+
+/// Legacy Perforce filetype names with their base type and modifiers.
+const LEGACY_TYPES: [(&str, &str, &str); 16] = [
+    ("ctempobj", "binary", "Sw"),
+    ("ctext", "text", "C"),
+    ("cxtext", "text", "Cx"),
+    ("ktext", "text", "k"),
+    ("kxtext", "text", "kx"),
+    ("ltext", "text", "F"),
+    ("tempobj", "binary", "FSw"),
+    ("ubinary", "binary", "F"),
+    ("uresource", "resource", "F"),
+    ("uxbinary", "binary", "Fx"),
+    ("xbinary", "binary", "x"),
+    ("xltext", "text", "Fx"),
+    ("xtempobj", "binary", "Swx"),
+    ("xtext", "text", "x"),
+    ("xunicode", "unicode", "x"),
+    ("xutf16", "utf16", "x"),
+];
+
+/// Perforce filetype split into base type and modifiers, for example `text+kx`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileType {
+    base: String,
+    modifiers: String,
+}
+
+impl FileType {
+    pub fn parse(file_type: &str) -> FileType {
+        let (name, modifiers) = match file_type.find('+') {
+            Some(index) => (&file_type[..index], &file_type[index + 1..]),
+            None => (file_type, ""),
+        };
+
+        match LEGACY_TYPES.iter().find(|&&(legacy, _, _)| legacy == name) {
+            Some(&(_, base, legacy_modifiers)) => FileType {
+                base: base.into(),
+                modifiers: format!("{}{}", legacy_modifiers, modifiers),
+            },
+            None => FileType {
+                base: name.into(),
+                modifiers: modifiers.into(),
+            },
+        }
+    }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    pub fn has_modifier(&self, modifier: char) -> bool {
+        self.modifiers.contains(modifier)
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.has_modifier('x')
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.base == "symlink"
+    }
+}
+
+impl Display for FileType {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        if self.modifiers.is_empty() {
+            write!(f, "{}", self.base)
+        } else {
+            write!(f, "{}+{}", self.base, self.modifiers)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_file_types() {
+        let file_type = FileType::parse("text+kx");
+
+        assert_eq!(file_type.base(), "text");
+        assert!(file_type.has_modifier('k'));
+        assert!(file_type.is_executable());
+        assert!(!file_type.is_symlink());
+        assert_eq!(file_type.to_string(), "text+kx");
+
+        let legacy = FileType::parse("kxtext+w");
+
+        assert_eq!(legacy.base(), "text");
+        assert!(legacy.has_modifier('k'));
+        assert!(legacy.has_modifier('w'));
+        assert!(legacy.is_executable());
+        assert_eq!(legacy.to_string(), "text+kxw");
+
+        assert!(FileType::parse("symlink").is_symlink());
+        assert_eq!(FileType::parse("binary").to_string(), "binary");
+    }
+}
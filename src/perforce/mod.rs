@@ -1,7 +1,15 @@
 mod client;
 mod error;
+mod filetype;
+mod view;
 
 pub use self::client::Change;
+pub use self::client::ChangeFile;
+pub use self::client::ClientSpec;
 pub use self::client::PerforceClient;
 pub use self::error::PerforceError;
 pub use self::error::PerforceResult;
+pub use self::filetype::FileType;
+pub use self::view::client_options;
+pub use self::view::client_view;
+pub use self::view::CLIENT_OPTIONS;
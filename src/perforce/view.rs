@@ -0,0 +1,132 @@
+use std::path::Path;
+
+use config::MappingConfig;
+
+use super::PerforceError;
+use super::PerforceResult;
+
+/// Workspace options in `Options` field order with disabled forms.
+pub const CLIENT_OPTIONS: [(&str, &str); 6] = [
+    ("allwrite", "noallwrite"),
+    ("clobber", "noclobber"),
+    ("compress", "nocompress"),
+    ("locked", "unlocked"),
+    ("modtime", "nomodtime"),
+    ("rmdir", "normdir"),
+];
+
+/// Generates workspace view lines for all mapping sources. Every source is mapped into its target
+/// subdirectory of mapping local directory, local directories must be inside workspace root.
+pub fn client_view(
+    client: &str,
+    root: &str,
+    mappings: &[MappingConfig],
+) -> PerforceResult<Vec<String>> {
+    let mut view = Vec::new();
+
+    for mapping in mappings {
+        let local_directory = Path::new(mapping.local_directory())
+            .strip_prefix(root)
+            .map_err(|_| PerforceError::local_path_outside_root(mapping.local_directory()))?;
+
+        for source in mapping.sources() {
+            let mut client_path = format!("//{}", client);
+
+            for component in local_directory
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                .chain(
+                    source
+                        .target_subdir()
+                        .split('/')
+                        .filter(|part| !part.is_empty())
+                        .map(String::from),
+                )
+            {
+                client_path.push('/');
+                client_path.push_str(&component);
+            }
+
+            view.push(format!(
+                "{} {}",
+                quote(&format!(
+                    "{}/...",
+                    source.depot_path().trim_end_matches('/')
+                )),
+                quote(&format!("{}/...", client_path))
+            ));
+        }
+    }
+
+    Ok(view)
+}
+
+/// Generates `Options` field value with given options enabled and all other disabled.
+pub fn client_options(enabled: &[String]) -> String {
+    CLIENT_OPTIONS
+        .iter()
+        .map(|&(option, disabled)| {
+            if enabled.iter().any(|enabled| enabled == option) {
+                option
+            } else {
+                disabled
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn quote(path: &str) -> String {
+    if path.contains(char::is_whitespace) {
+        format!("\"{}\"", path)
+    } else {
+        path.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use testing::config;
+
+    #[test]
+    fn maps_sources_into_subdirectories() {
+        let config = config(
+            "mappings:
+  - depot_directory: //depot/project/
+    bookmark: project
+    local_directory: /work/project
+  - sources:
+      - depot_path: //depot/libA/
+        target_subdir: libA
+      - depot_path: //depot/My Lib
+        target_subdir: lib/my
+    bookmark: monorepo
+    local_directory: /work/mono repo
+",
+        );
+
+        assert_eq!(
+            client_view("sync", "/work", config.mappings()).unwrap(),
+            vec![
+                "//depot/project/... //sync/project/...",
+                "//depot/libA/... \"//sync/mono repo/libA/...\"",
+                "\"//depot/My Lib/...\" \"//sync/mono repo/lib/my/...\"",
+            ]
+        );
+        assert!(client_view("sync", "/other", config.mappings()).is_err());
+    }
+
+    #[test]
+    fn disables_other_options() {
+        assert_eq!(
+            client_options(&["clobber".into(), "rmdir".into()]),
+            "noallwrite clobber nocompress unlocked nomodtime rmdir"
+        );
+        assert_eq!(
+            client_options(&[]),
+            "noallwrite noclobber nocompress unlocked nomodtime normdir"
+        );
+    }
+}
@@ -0,0 +1,45 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
+use std::io::Error as IoError;
+
+#[derive(Debug)]
+pub enum ServerError {
+    BindError { message: String },
+    IoError { message: String },
+}
+
+impl ServerError {
+    pub fn bind_error(error: IoError) -> ServerError {
+        ServerError::BindError {
+            message: format!("{}", error),
+        }
+    }
+
+    pub fn io_error(error: IoError) -> ServerError {
+        ServerError::IoError {
+            message: format!("{}", error),
+        }
+    }
+}
+
+impl Display for ServerError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            ServerError::BindError { message } => write!(f, "Bind error: {}", message),
+            ServerError::IoError { message } => write!(f, "IO error: {}", message),
+        }
+    }
+}
+
+impl Error for ServerError {
+    fn description(&self) -> &str {
+        match self {
+            ServerError::BindError { .. } => "Bind error",
+            ServerError::IoError { .. } => "IO error",
+        }
+    }
+}
+
+pub type ServerResult<T> = Result<T, ServerError>;
@@ -0,0 +1,380 @@
+mod error;
+
+pub use self::error::ServerError;
+pub use self::error::ServerResult;
+
+use libc;
+
+use std::fs;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Error as IoError;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+#[cfg(target_os = "linux")]
+use std::mem;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use control::Control;
+use metrics;
+use status::SyncStatus;
+
+/// Time limit for reading request and writing response, so stalled client does not block
+/// single-threaded HTTP and socket servers.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Starts HTTP server in background thread.
+pub fn start(address: &str, status: Arc<SyncStatus>) -> ServerResult<()> {
+    info!("Starting HTTP server, address = {}.", address);
+    let listener = TcpListener::bind(address).map_err(ServerError::bind_error)?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(err) = handle_http(stream, &status) {
+                        warn!("HTTP request failed: {}", err);
+                    }
+                }
+                Err(err) => warn!("HTTP connection failed: {}", err),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Starts Unix socket server in background thread. Every connection receives single command
+/// line and gets response.
+pub fn start_socket(
+    path: &str,
+    status: Arc<SyncStatus>,
+    control: Arc<Control>,
+) -> ServerResult<()> {
+    info!("Starting socket server, path = {}.", path);
+    if Path::new(path).exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(ServerError::bind_error(IoError::new(
+                ErrorKind::AddrInUse,
+                format!("socket {} is used by another process", path),
+            )));
+        }
+
+        fs::remove_file(path).map_err(ServerError::bind_error)?;
+    }
+
+    let listener = UnixListener::bind(path).map_err(ServerError::bind_error)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+        .map_err(ServerError::bind_error)?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(err) = handle_socket(stream, &status, &control) {
+                        warn!("Socket request failed: {}", err);
+                    }
+                }
+                Err(err) => warn!("Socket connection failed: {}", err),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Sends command to Unix socket server and returns response.
+pub fn socket_request(path: &str, command: &str) -> ServerResult<String> {
+    let mut stream = UnixStream::connect(path).map_err(ServerError::io_error)?;
+    let mut response = String::new();
+
+    writeln!(stream, "{}", command).map_err(ServerError::io_error)?;
+    stream
+        .read_to_string(&mut response)
+        .map_err(ServerError::io_error)?;
+
+    Ok(response)
+}
+
+/// Sends GET request to HTTP server and returns response body.
+pub fn http_request(address: &str, path: &str) -> ServerResult<String> {
+    let mut stream = TcpStream::connect(address).map_err(ServerError::io_error)?;
+    let mut response = String::new();
+
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, address
+    )
+    .map_err(ServerError::io_error)?;
+    stream
+        .read_to_string(&mut response)
+        .map_err(ServerError::io_error)?;
+
+    match response.find("\r\n\r\n") {
+        Some(index) => Ok(response[index + 4..].into()),
+        None => Ok(response),
+    }
+}
+
+fn handle_http(mut stream: TcpStream, status: &SyncStatus) -> ServerResult<()> {
+    let mut request_line = String::new();
+
+    stream
+        .set_read_timeout(Some(CONNECTION_TIMEOUT))
+        .map_err(ServerError::io_error)?;
+    stream
+        .set_write_timeout(Some(CONNECTION_TIMEOUT))
+        .map_err(ServerError::io_error)?;
+
+    {
+        let mut reader = BufReader::new(&mut stream);
+
+        reader
+            .read_line(&mut request_line)
+            .map_err(ServerError::io_error)?;
+
+        loop {
+            let mut header = String::new();
+
+            reader
+                .read_line(&mut header)
+                .map_err(ServerError::io_error)?;
+
+            if header.trim().is_empty() {
+                break;
+            }
+        }
+    }
+
+    debug!("HTTP request {:?}.", request_line.trim());
+    let mut parts = request_line.split_whitespace();
+
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => respond(
+            &mut stream,
+            "200 OK",
+            "text/plain; version=0.0.4",
+            &metrics::render(),
+        ),
+        (Some("GET"), Some("/status")) => {
+            respond(&mut stream, "200 OK", "application/json", &status.to_json())
+        }
+        (Some("GET"), Some(_)) => {
+            respond(&mut stream, "404 Not Found", "text/plain", "Not found\n")
+        }
+        _ => respond(
+            &mut stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            "Method not allowed\n",
+        ),
+    }
+}
+
+fn handle_socket(
+    mut stream: UnixStream,
+    status: &SyncStatus,
+    control: &Control,
+) -> ServerResult<()> {
+    let mut command = String::new();
+
+    stream
+        .set_read_timeout(Some(CONNECTION_TIMEOUT))
+        .map_err(ServerError::io_error)?;
+    stream
+        .set_write_timeout(Some(CONNECTION_TIMEOUT))
+        .map_err(ServerError::io_error)?;
+
+    if !is_same_user(&stream)? {
+        writeln!(stream, "Permission denied").map_err(ServerError::io_error)?;
+
+        return stream.flush().map_err(ServerError::io_error);
+    }
+
+    {
+        let mut reader = BufReader::new(&mut stream);
+
+        reader
+            .read_line(&mut command)
+            .map_err(ServerError::io_error)?;
+    }
+
+    debug!("Socket command {:?}.", command.trim());
+    let response = match command.split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["status"] => status.to_json(),
+        ["metrics"] => metrics::render(),
+        ["pause", bookmark] if status.contains(bookmark) => {
+            control.pause(bookmark);
+            status.set_paused(bookmark, true);
+
+            "OK".into()
+        }
+        ["resume", bookmark] if status.contains(bookmark) => {
+            control.resume(bookmark);
+            status.set_paused(bookmark, false);
+
+            "OK".into()
+        }
+        ["pause", bookmark] | ["resume", bookmark] => format!("Unknown mapping: {}", bookmark),
+        ["sync"] => {
+            control.force_sync();
+
+            "OK".into()
+        }
+        ["drain"] => {
+            control.drain();
+
+            "OK".into()
+        }
+        ["reload"] => {
+            control.reload();
+
+            "OK".into()
+        }
+        _ => format!("Unknown command: {}", command.trim()),
+    };
+
+    writeln!(stream, "{}", response).map_err(ServerError::io_error)?;
+
+    stream.flush().map_err(ServerError::io_error)
+}
+
+/// Checks that peer of socket connection runs as the same user as this process. Socket file
+/// is created before its permissions are restricted, so connection may come from anyone.
+fn is_same_user(stream: &UnixStream) -> ServerResult<bool> {
+    Ok(peer_uid(stream)? == unsafe { libc::getuid() })
+}
+
+/// Returns user id of socket peer, `SO_PEERCRED` is available on Linux only.
+#[cfg(target_os = "linux")]
+fn peer_uid(stream: &UnixStream) -> ServerResult<libc::uid_t> {
+    let mut credentials = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut length = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut credentials as *mut libc::ucred as *mut libc::c_void,
+            &mut length,
+        )
+    };
+
+    if result != 0 {
+        return Err(ServerError::io_error(IoError::last_os_error()));
+    }
+
+    Ok(credentials.uid)
+}
+
+/// Returns user id of socket peer using `getpeereid` of BSD and macOS.
+#[cfg(not(target_os = "linux"))]
+fn peer_uid(stream: &UnixStream) -> ServerResult<libc::uid_t> {
+    let mut uid = 0;
+    let mut gid = 0;
+    let result = unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) };
+
+    if result != 0 {
+        return Err(ServerError::io_error(IoError::last_os_error()));
+    }
+
+    Ok(uid)
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> ServerResult<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+    .map_err(ServerError::io_error)?;
+
+    stream.flush().map_err(ServerError::io_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::net::UnixListener;
+
+    use testing::temp_path;
+
+    fn socket_path(name: &str) -> String {
+        let path = temp_path(&format!("{}.sock", name));
+        let _ = fs::remove_file(&path);
+
+        path.display().to_string()
+    }
+
+    #[test]
+    fn socket_is_private_and_answers() {
+        let path = socket_path("private");
+
+        start_socket(
+            &path,
+            Arc::new(SyncStatus::new(&[])),
+            Arc::new(Control::new()),
+        )
+        .unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(socket_request(&path, "sync").unwrap(), "OK\n");
+    }
+
+    #[test]
+    fn live_socket_is_kept() {
+        let path = socket_path("live");
+        let _listener = UnixListener::bind(&path).unwrap();
+
+        assert!(start_socket(
+            &path,
+            Arc::new(SyncStatus::new(&[])),
+            Arc::new(Control::new())
+        )
+        .is_err());
+        assert!(Path::new(&path).exists());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn dead_socket_is_replaced() {
+        let path = socket_path("dead");
+
+        drop(UnixListener::bind(&path).unwrap());
+        start_socket(
+            &path,
+            Arc::new(SyncStatus::new(&[])),
+            Arc::new(Control::new()),
+        )
+        .unwrap();
+
+        assert_eq!(socket_request(&path, "drain").unwrap(), "OK\n");
+    }
+}
@@ -0,0 +1,216 @@
+use serde_json;
+use time::get_time;
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+use config::MappingConfig;
+
+/// Synchronization state of all mappings shared between worker and status endpoints.
+#[derive(Debug)]
+pub struct SyncStatus {
+    mappings: Mutex<BTreeMap<String, MappingStatus>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MappingStatus {
+    depot_directory: String,
+    bookmark: String,
+    last_imported_change: Option<u32>,
+    head_change: Option<u32>,
+    last_error: Option<String>,
+    last_success: Option<i64>,
+    running: bool,
+    paused: bool,
+}
+
+impl SyncStatus {
+    pub fn new(mappings: &[MappingConfig]) -> SyncStatus {
+        let mappings = mappings
+            .iter()
+            .map(|mapping| {
+                (
+                    mapping.bookmark().clone(),
+                    MappingStatus::new(&mapping.depot_path(), mapping.bookmark()),
+                )
+            })
+            .collect();
+
+        SyncStatus {
+            mappings: Mutex::new(mappings),
+        }
+    }
+
+    /// Replaces mapping list after configuration reload. State of mappings with the same bookmark
+    /// is kept.
+    pub fn reset(&self, mappings: &[MappingConfig]) {
+        let mut current = self.lock();
+        let updated = mappings
+            .iter()
+            .map(|mapping| {
+                let status = match current.remove(mapping.bookmark()) {
+                    Some(mut status) => {
+                        status.depot_directory = mapping.depot_path();
+                        status
+                    }
+                    None => MappingStatus::new(&mapping.depot_path(), mapping.bookmark()),
+                };
+
+                (mapping.bookmark().clone(), status)
+            })
+            .collect();
+
+        *current = updated;
+    }
+
+    pub fn contains(&self, bookmark: &str) -> bool {
+        self.lock().contains_key(bookmark)
+    }
+
+    pub fn set_paused(&self, bookmark: &str, paused: bool) {
+        self.update(bookmark, |status| status.paused = paused);
+    }
+
+    pub fn started(&self, bookmark: &str) {
+        self.update(bookmark, |status| status.running = true);
+    }
+
+    pub fn succeeded(&self, bookmark: &str) {
+        let now = get_time().sec;
+
+        self.update(bookmark, |status| {
+            status.running = false;
+            status.last_error = None;
+            status.last_success = Some(now);
+        });
+    }
+
+    pub fn failed(&self, bookmark: &str, error: &str) {
+        self.update(bookmark, |status| {
+            status.running = false;
+            status.last_error = Some(error.into());
+        });
+    }
+
+    pub fn set_last_imported_change(&self, bookmark: &str, change: Option<u32>) {
+        self.update(bookmark, |status| status.last_imported_change = change);
+    }
+
+    pub fn set_head_change(&self, bookmark: &str, change: Option<u32>) {
+        self.update(bookmark, |status| status.head_change = change);
+    }
+
+    pub fn mappings(&self) -> Vec<MappingStatus> {
+        self.lock().values().cloned().collect()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.mappings()).unwrap_or_else(|_| "[]".into())
+    }
+
+    fn update<F>(&self, bookmark: &str, callback: F)
+    where
+        F: FnOnce(&mut MappingStatus),
+    {
+        if let Some(status) = self.lock().get_mut(bookmark) {
+            callback(status);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, MappingStatus>> {
+        match self.mappings.lock() {
+            Ok(mappings) => mappings,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl MappingStatus {
+    fn new(depot_directory: &str, bookmark: &str) -> MappingStatus {
+        MappingStatus {
+            depot_directory: depot_directory.into(),
+            bookmark: bookmark.into(),
+            last_imported_change: None,
+            head_change: None,
+            last_error: None,
+            last_success: None,
+            running: false,
+            paused: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use config::Config;
+    use testing::config;
+
+    fn mappings(text: &str) -> Config {
+        config(&format!("mappings:\n{}", text))
+    }
+
+    #[test]
+    fn tracks_mapping_state() {
+        let config = mappings(
+            "  - depot_directory: //depot/main/
+    bookmark: main
+    local_directory: /work/main
+",
+        );
+        let status = SyncStatus::new(config.mappings());
+
+        status.started("main");
+        status.failed("main", "Perforce error");
+        status.set_last_imported_change("main", Some(42));
+        status.set_head_change("unknown", Some(50));
+
+        let mapping = &status.mappings()[0];
+
+        assert!(!mapping.running);
+        assert_eq!(mapping.last_error.as_ref().unwrap(), "Perforce error");
+        assert_eq!(mapping.last_imported_change, Some(42));
+
+        status.succeeded("main");
+
+        let mapping = &status.mappings()[0];
+
+        assert_eq!(mapping.last_error, None);
+        assert!(mapping.last_success.is_some());
+        assert!(status.to_json().contains("\"last_imported_change\": 42"));
+    }
+
+    #[test]
+    fn keeps_state_on_reset() {
+        let config = mappings(
+            "  - depot_directory: //depot/main/
+    bookmark: main
+    local_directory: /work/main
+",
+        );
+        let status = SyncStatus::new(config.mappings());
+
+        status.set_last_imported_change("main", Some(42));
+        status.reset(
+            mappings(
+                "  - depot_directory: //depot/trunk/
+    bookmark: main
+    local_directory: /work/main
+  - depot_directory: //depot/release/
+    bookmark: release
+    local_directory: /work/release
+",
+            )
+            .mappings(),
+        );
+
+        let updated = status.mappings();
+
+        assert_eq!(updated.len(), 2);
+        assert_eq!(updated[0].depot_directory, "//depot/trunk/");
+        assert_eq!(updated[0].last_imported_change, Some(42));
+        assert!(status.contains("release"));
+    }
+}
@@ -0,0 +1,36 @@
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+use config::Config;
+
+const PERFORCE: &str = "
+perforce:
+  command: p4
+  work_dir: /work
+  client: client
+  port: tcp:perforce:1666
+  user: user
+  password: password
+  ignore: .p4ignore
+";
+
+/// Returns text of test configuration with Perforce settings and Mercurial `command`, `text`
+/// holds the rest of settings.
+pub fn config_text(command: &str, text: &str) -> String {
+    format!("{}mercurial:\n  command: {}\n{}", PERFORCE, command, text)
+}
+
+/// Reads test configuration with default Mercurial command, update interval and batch size,
+/// `text` holds mappings and other settings.
+pub fn config(text: &str) -> Config {
+    let text = format!("update_interval: 60\nbatch_size: 10\n{}", text);
+
+    Config::from_reader(config_text("hg", &text).as_bytes()).unwrap()
+}
+
+/// Returns path in temporary directory which is unique for test process, `name` tells tests
+/// apart.
+pub fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("perforce-sync-test-{}-{}", process::id(), name))
+}
@@ -0,0 +1,36 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
+use std::io::Error as IoError;
+
+#[derive(Debug)]
+pub enum TransformError {
+    IoError { message: String },
+}
+
+impl TransformError {
+    pub fn io_error(error: IoError) -> TransformError {
+        TransformError::IoError {
+            message: format!("{}", error),
+        }
+    }
+}
+
+impl Display for TransformError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            TransformError::IoError { message } => write!(f, "IO error: {}", message),
+        }
+    }
+}
+
+impl Error for TransformError {
+    fn description(&self) -> &str {
+        match self {
+            TransformError::IoError { .. } => "IO error",
+        }
+    }
+}
+
+pub type TransformResult<T> = Result<T, TransformError>;
@@ -0,0 +1,217 @@
+mod error;
+
+pub use self::error::TransformError;
+pub use self::error::TransformResult;
+
+use encoding_rs::UTF_16BE;
+use encoding_rs::UTF_16LE;
+use regex::bytes::Captures;
+use regex::bytes::Regex;
+
+use std::fs;
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+lazy_static! {
+    static ref KEYWORDS: Regex = Regex::new(
+        r"\$(Id|Header|Date|DateUTC|DateTime|DateTimeUTC|DateTimeTZ|Change|File|Revision|Author):[^$\n]*\$"
+    )
+    .unwrap();
+}
+
+/// Collapses expanded RCS keywords, for example `$Change: 123 $` to `$Change$`. With
+/// `only_id` set only `$Id$` and `$Header$` are collapsed, as Perforce expands for `+ko` files.
+/// Returns `true` if file was changed.
+pub fn collapse_keywords(path: &Path, only_id: bool) -> TransformResult<bool> {
+    let metadata = fs::symlink_metadata(path).map_err(TransformError::io_error)?;
+
+    if !metadata.file_type().is_file() {
+        return Ok(false);
+    }
+
+    let content = fs::read(path).map_err(TransformError::io_error)?;
+    let collapsed = KEYWORDS.replace_all(&content, |captures: &Captures| {
+        let keyword = &captures[1];
+
+        if only_id && keyword != b"Id" && keyword != b"Header" {
+            captures[0].to_vec()
+        } else {
+            [b"$", keyword, b"$"].concat()
+        }
+    });
+
+    if collapsed[..] == content[..] {
+        return Ok(false);
+    }
+
+    debug!("Collapsing keywords in {}.", path.display());
+    rewrite(path, &collapsed, metadata.permissions().mode())?;
+
+    Ok(true)
+}
+
+/// Converts UTF-16 file to UTF-8 and normalizes line endings to LF. UTF-16 byte order is taken
+/// from byte order mark, little endian is assumed without it. Returns `true` if file was changed.
+pub fn convert(path: &Path, utf8: bool, lf: bool) -> TransformResult<bool> {
+    let metadata = fs::symlink_metadata(path).map_err(TransformError::io_error)?;
+
+    if !metadata.file_type().is_file() {
+        return Ok(false);
+    }
+
+    let content = fs::read(path).map_err(TransformError::io_error)?;
+    let mut converted = if utf8 {
+        decode_utf16(&content)
+    } else {
+        content.clone()
+    };
+
+    if lf {
+        converted = normalize_line_endings(&converted);
+    }
+
+    if converted == content {
+        return Ok(false);
+    }
+
+    debug!("Converting {}.", path.display());
+    rewrite(path, &converted, metadata.permissions().mode())?;
+
+    Ok(true)
+}
+
+/// Replaces content of synced file. Perforce syncs files read-only unless workspace has
+/// `allwrite` option, so owner write permission is added while writing and mode is restored.
+fn rewrite(path: &Path, content: &[u8], mode: u32) -> TransformResult<()> {
+    let read_only = mode & 0o200 == 0;
+
+    if read_only {
+        fs::set_permissions(path, Permissions::from_mode(mode | 0o200))
+            .map_err(TransformError::io_error)?;
+    }
+
+    let result = fs::write(path, content).map_err(TransformError::io_error);
+
+    if read_only {
+        fs::set_permissions(path, Permissions::from_mode(mode))
+            .map_err(TransformError::io_error)?;
+    }
+
+    result
+}
+
+fn decode_utf16(content: &[u8]) -> Vec<u8> {
+    let (encoding, content) = match content {
+        [0xFE, 0xFF, rest @ ..] => (UTF_16BE, rest),
+        [0xFF, 0xFE, rest @ ..] => (UTF_16LE, rest),
+        _ => (UTF_16LE, content),
+    };
+    let (text, _) = encoding.decode_without_bom_handling(content);
+
+    text.into_owned().into_bytes()
+}
+
+fn normalize_line_endings(content: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(content.len());
+    let mut bytes = content.iter().peekable();
+
+    while let Some(&byte) = bytes.next() {
+        if byte != b'\r' || bytes.peek() != Some(&&b'\n') {
+            result.push(byte);
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use testing::temp_path;
+
+    fn temp_file(name: &str, content: &[u8]) -> PathBuf {
+        let path = temp_path(name);
+        fs::write(&path, content).unwrap();
+
+        path
+    }
+
+    #[test]
+    fn collapses_keywords() {
+        let path = temp_file(
+            "keywords",
+            b"$Id: //depot/a.c#3 $\n$Change: 123 $ $Author: alice $\n$Header$\n",
+        );
+
+        assert!(collapse_keywords(&path, false).unwrap());
+        assert_eq!(
+            fs::read(&path).unwrap(),
+            b"$Id$\n$Change$ $Author$\n$Header$\n"
+        );
+        assert!(!collapse_keywords(&path, false).unwrap());
+
+        fs::write(&path, b"$Id: //depot/a.c#3 $ $Change: 123 $\n").unwrap();
+
+        assert!(collapse_keywords(&path, true).unwrap());
+        assert_eq!(fs::read(&path).unwrap(), b"$Id$ $Change: 123 $\n");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn collapses_keywords_of_read_only_file() {
+        let path = temp_file("keywords-read-only", b"$Change: 123 $\n");
+
+        fs::set_permissions(&path, Permissions::from_mode(0o444)).unwrap();
+
+        assert!(collapse_keywords(&path, false).unwrap());
+        assert_eq!(fs::read(&path).unwrap(), b"$Change$\n");
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o444
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn converts_utf16_and_line_endings() {
+        let path = temp_file("utf16", b"\xFF\xFEa\x00\r\x00\n\x00\xE9\x00");
+
+        assert!(convert(&path, true, true).unwrap());
+        assert_eq!(fs::read(&path).unwrap(), "a\n\u{e9}".as_bytes());
+
+        fs::write(&path, b"\xFE\xFF\x00a\x00\r\x00\n").unwrap();
+
+        assert!(convert(&path, true, false).unwrap());
+        assert_eq!(fs::read(&path).unwrap(), b"a\r\n");
+
+        fs::write(&path, b"one\r\ntwo\rthree\n").unwrap();
+
+        assert!(convert(&path, false, true).unwrap());
+        assert_eq!(fs::read(&path).unwrap(), b"one\ntwo\rthree\n");
+        assert!(!convert(&path, false, true).unwrap());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn converts_read_only_file() {
+        let path = temp_file("convert-read-only", b"one\r\ntwo\r\n");
+
+        fs::set_permissions(&path, Permissions::from_mode(0o444)).unwrap();
+
+        assert!(convert(&path, false, true).unwrap());
+        assert_eq!(fs::read(&path).unwrap(), b"one\ntwo\n");
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o444
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+use md5;
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+/// File expected in Mercurial revision. Content is compared only for files with digest.
+#[derive(Debug)]
+pub struct ExpectedFile {
+    path: String,
+    digest: Option<(String, u64)>,
+}
+
+/// Differences between Mercurial revision and Perforce change.
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    missing: Vec<String>,
+    extra: Vec<String>,
+    differing: Vec<String>,
+}
+
+impl ExpectedFile {
+    pub fn new(path: String, digest: Option<(String, u64)>) -> ExpectedFile {
+        ExpectedFile { path, digest }
+    }
+}
+
+impl VerifyReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.differing.is_empty()
+    }
+
+    /// Files existing in Perforce but not tracked by Mercurial.
+    pub fn missing(&self) -> &[String] {
+        &self.missing
+    }
+
+    /// Files tracked by Mercurial but not existing in Perforce.
+    pub fn extra(&self) -> &[String] {
+        &self.extra
+    }
+
+    /// Files with different size or digest.
+    pub fn differing(&self) -> &[String] {
+        &self.differing
+    }
+}
+
+/// Compares files of Mercurial revision with expected files. `tracked` is Mercurial manifest of
+/// revision, `root` is directory with revision files: working copy or archive. Mercurial own
+/// files (`.hgignore`, `.hgtags` and so on) are not compared, large file standins are compared
+/// as large files.
+pub fn compare(root: &Path, expected: &[ExpectedFile], tracked: &[String]) -> VerifyReport {
+    let tracked: BTreeSet<&str> = tracked
+        .iter()
+        .map(|path| path.strip_prefix(".hglf/").unwrap_or(path))
+        .filter(|path| !path.starts_with(".hg"))
+        .collect();
+    let expected: BTreeMap<&str, &ExpectedFile> = expected
+        .iter()
+        .map(|file| (file.path.as_str(), file))
+        .collect();
+    let mut report = VerifyReport::default();
+
+    for (path, file) in &expected {
+        if !tracked.contains(path) {
+            report.missing.push(path.to_string());
+        } else if let Some((ref digest, size)) = file.digest {
+            if !matches(&root.join(path), digest, size) {
+                report.differing.push(path.to_string());
+            }
+        }
+    }
+
+    for path in tracked {
+        if !expected.contains_key(path) {
+            report.extra.push(path.into());
+        }
+    }
+
+    report
+}
+
+fn matches(path: &Path, digest: &str, size: u64) -> bool {
+    match fs::read(path) {
+        Ok(content) => {
+            content.len() as u64 == size
+                && format!("{:X}", md5::compute(&content)).eq_ignore_ascii_case(digest)
+        }
+        Err(err) => {
+            debug!("Reading {} failed: {}.", path.display(), err);
+
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use testing::temp_path;
+
+    #[test]
+    fn compares_revision_files() {
+        let root = temp_path("verify");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/same.c"), b"same\n").unwrap();
+        fs::write(root.join("src/changed.c"), b"changed\n").unwrap();
+        fs::write(root.join("big.bin"), b"large\n").unwrap();
+
+        let digest = |content: &[u8]| Some((format!("{:x}", md5::compute(content)), 5));
+        let expected = vec![
+            ExpectedFile::new("src/same.c".into(), digest(b"same\n")),
+            ExpectedFile::new("src/changed.c".into(), digest(b"other\n")),
+            ExpectedFile::new(
+                "big.bin".into(),
+                Some((format!("{:X}", md5::compute(b"large\n")), 6)),
+            ),
+            ExpectedFile::new("src/keyword.c".into(), None),
+            ExpectedFile::new("src/missing.c".into(), None),
+        ];
+        let tracked: Vec<String> = vec![
+            ".hgtags",
+            ".hglf/big.bin",
+            "src/same.c",
+            "src/changed.c",
+            "src/keyword.c",
+            "src/extra.c",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+
+        let report = compare(&root, &expected, &tracked);
+
+        assert!(!report.is_clean());
+        assert_eq!(report.missing(), ["src/missing.c"]);
+        assert_eq!(report.extra(), ["src/extra.c"]);
+        assert_eq!(report.differing(), ["src/changed.c"]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -3,16 +3,46 @@ use std::fmt::Display;
 use std::fmt::Formatter;
 use std::fmt::Result as FmtResult;
 
+use audit::AuditError;
+use journal::JournalError;
+use lock::LockError;
 use mercurial::MercurialError;
 use perforce::PerforceError;
+use transform::TransformError;
 
 #[derive(Debug)]
 pub enum WorkerError {
+    AuditError { message: String },
+    JournalError { message: String },
+    LockError { message: String },
     MercurialError { message: String },
     PerforceError { message: String },
+    TransformError { message: String },
+    UnknownMapping { bookmark: String },
+    AlreadyImported { bookmark: String },
+    NotImported { bookmark: String },
+    VerificationFailed { change: u32 },
 }
 
 impl WorkerError {
+    pub fn audit_error(error: AuditError) -> WorkerError {
+        WorkerError::AuditError {
+            message: format!("{}", error),
+        }
+    }
+
+    pub fn journal_error(error: JournalError) -> WorkerError {
+        WorkerError::JournalError {
+            message: format!("{}", error),
+        }
+    }
+
+    pub fn lock_error(error: LockError) -> WorkerError {
+        WorkerError::LockError {
+            message: format!("{}", error),
+        }
+    }
+
     pub fn mercurial_error(error: MercurialError) -> WorkerError {
         WorkerError::MercurialError {
             message: error.description().into(),
@@ -21,7 +51,51 @@ impl WorkerError {
 
     pub fn perforce_error(error: PerforceError) -> WorkerError {
         WorkerError::PerforceError {
-            message: error.description().into(),
+            message: format!("{}", error),
+        }
+    }
+
+    pub fn transform_error(error: TransformError) -> WorkerError {
+        WorkerError::TransformError {
+            message: format!("{}", error),
+        }
+    }
+
+    pub fn unknown_mapping(bookmark: &str) -> WorkerError {
+        WorkerError::UnknownMapping {
+            bookmark: bookmark.into(),
+        }
+    }
+
+    pub fn already_imported(bookmark: &str) -> WorkerError {
+        WorkerError::AlreadyImported {
+            bookmark: bookmark.into(),
+        }
+    }
+
+    pub fn not_imported(bookmark: &str) -> WorkerError {
+        WorkerError::NotImported {
+            bookmark: bookmark.into(),
+        }
+    }
+
+    pub fn verification_failed(change: u32) -> WorkerError {
+        WorkerError::VerificationFailed { change }
+    }
+
+    /// Short kind of error, used as metrics label.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            WorkerError::AuditError { .. } => "Audit error",
+            WorkerError::JournalError { .. } => "Journal error",
+            WorkerError::LockError { .. } => "Lock error",
+            WorkerError::MercurialError { .. } => "Mercurial error",
+            WorkerError::PerforceError { .. } => "Perforce error",
+            WorkerError::TransformError { .. } => "Transform error",
+            WorkerError::UnknownMapping { .. } => "Unknown mapping",
+            WorkerError::AlreadyImported { .. } => "Already imported",
+            WorkerError::NotImported { .. } => "Not imported",
+            WorkerError::VerificationFailed { .. } => "Verification failed",
         }
     }
 }
@@ -29,18 +103,31 @@ impl WorkerError {
 impl Display for WorkerError {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         match self {
+            WorkerError::AuditError { message } => write!(f, "Audit error: {}", message),
+            WorkerError::JournalError { message } => write!(f, "Journal error: {}", message),
+            WorkerError::LockError { message } => write!(f, "Lock error: {}", message),
             WorkerError::MercurialError { message } => write!(f, "Mercurial error: {}", message),
             WorkerError::PerforceError { message } => write!(f, "Perforce error: {}", message),
+            WorkerError::TransformError { message } => write!(f, "Transform error: {}", message),
+            WorkerError::UnknownMapping { bookmark } => {
+                write!(f, "No mapping for bookmark {}", bookmark)
+            }
+            WorkerError::AlreadyImported { bookmark } => {
+                write!(f, "Changes are already imported into bookmark {}", bookmark)
+            }
+            WorkerError::NotImported { bookmark } => {
+                write!(f, "No changes are imported into bookmark {}", bookmark)
+            }
+            WorkerError::VerificationFailed { change } => {
+                write!(f, "Commit of change {} differs from Perforce", change)
+            }
         }
     }
 }
 
 impl Error for WorkerError {
     fn description(&self) -> &str {
-        match self {
-            WorkerError::MercurialError { .. } => "Mercurial error",
-            WorkerError::PerforceError { .. } => "Perforce error",
-        }
+        self.kind()
     }
 }
 
@@ -1,38 +1,146 @@
 mod error;
 
-use std::thread::sleep;
+use time::get_time;
+use time::now_utc;
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
 
+use audit::AuditLog;
+use audit::AuditRecord;
+use authors::AuthorResolver;
 use config::Config;
+use config::IgnoredFiles;
+use config::ImportMode;
+use config::LeaseConfig;
+use config::LineEndings;
 use config::MappingConfig;
+use control::Control;
+use control::ControlExit;
+use journal::Journal;
+use journal::JournalEntry;
+use journal::JournalStep;
+use lock;
+use lock::Lock;
+use logging;
 use mercurial::MercurialClient;
+use message::format_change;
+use message::format_group;
+use message::format_link;
+use message::format_snapshot;
+use metrics;
+use notify::Notifier;
+use perforce::client_options;
+use perforce::client_view;
 use perforce::Change;
+use perforce::ChangeFile;
+use perforce::ClientSpec;
 use perforce::PerforceClient;
+use status::SyncStatus;
+use transform;
+use verify;
+use verify::ExpectedFile;
+use verify::VerifyReport;
 
 pub use self::error::WorkerError;
 pub use self::error::WorkerResult;
 
+/// Files of this size and larger are added as large files.
+const LARGE_FILE_SIZE: u64 = 10 * 1024 * 1024;
+/// Number of last commits of every bookmark checked by `verify` command without change range.
+const VERIFY_COMMITS: usize = 100;
+
+/// State of head change committed at once with its file list and message.
+struct CommitState<'c> {
+    /// First change to synchronize, whole workspace is synchronized to head without it.
+    first: Option<u32>,
+    head: u32,
+    latest: &'c Change,
+    changes: Vec<(&'c Change, &'c [ChangeFile])>,
+    files: &'c [ChangeFile],
+    message: String,
+    /// Last change of bookmark before commit, workspace is returned to it on rollback.
+    previous: Option<u32>,
+    pending_push: bool,
+}
+
 pub struct Worker<'a> {
     config: &'a Config,
+    status: Arc<SyncStatus>,
+    control: Arc<Control>,
+    notifier: Notifier<'a>,
+    authors: AuthorResolver<'a>,
 }
 
 impl<'a> Worker<'a> {
-    pub fn new(config: &'a Config) -> Worker<'a> {
-        Worker { config }
+    pub fn new(config: &'a Config, status: Arc<SyncStatus>, control: Arc<Control>) -> Worker<'a> {
+        Worker {
+            config,
+            status,
+            control,
+            notifier: Notifier::new(config.notifications()),
+            authors: AuthorResolver::new(config.authors()),
+        }
     }
 
-    pub fn start(&self) {
+    pub fn start(&self) -> ControlExit {
         let update_interval = Duration::from_secs(self.config.update_interval());
         let batch_size = self.config.batch_size();
 
+        if let Err(err) = self.check_workspace() {
+            error!("{}", err);
+        }
+
         loop {
             info!("Processing batch, batch_size = {}", batch_size);
             let now = Instant::now();
 
-            for mapping in self.config.mappings() {
-                if let Err(err) = self.process_mapping(mapping, batch_size) {
+            let is_leader = match self.config.lease() {
+                Some(lease) => self.acquire_lease(lease).unwrap_or_else(|err| {
                     error!("{}", err);
+
+                    false
+                }),
+                None => true,
+            };
+
+            if is_leader {
+                for mapping in self.config.mappings() {
+                    if let Some(exit) = self.control.take_exit() {
+                        return exit;
+                    }
+
+                    if self.control.is_paused(mapping.bookmark()) {
+                        info!("Mapping {} paused", mapping.bookmark());
+
+                        continue;
+                    }
+
+                    self.status.started(mapping.bookmark());
+                    logging::set_mapping(Some(mapping.bookmark()));
+
+                    match self.process_mapping(mapping, batch_size) {
+                        Ok(lag_changes) => {
+                            self.status.succeeded(mapping.bookmark());
+                            self.notifier.succeeded(mapping.bookmark(), lag_changes);
+                        }
+                        Err(err) => {
+                            error!("{}", err);
+
+                            metrics::failed(mapping.bookmark(), err.kind());
+                            self.status.failed(mapping.bookmark(), &err.to_string());
+                            self.notifier.failed(mapping.bookmark(), &err.to_string());
+                        }
+                    }
+
+                    logging::set_mapping(None);
                 }
             }
 
@@ -44,122 +152,2105 @@ impl<'a> Worker<'a> {
                 let delta = update_interval - elapsed;
 
                 info!("Sleeping for {}", delta.as_secs());
-                sleep(delta);
+                self.control.wait(delta);
+            }
+
+            if let Some(exit) = self.control.take_exit() {
+                return exit;
             }
         }
     }
 
-    fn process_mapping(&self, mapping: &MappingConfig, batch_size: usize) -> WorkerResult<()> {
-        info!(
-            "Processing mapping, depot_directory = {}",
-            mapping.depot_directory()
-        );
-        let depot_directory = mapping.depot_directory();
+    /// Compares Perforce workspace spec with root and view generated from mappings and reports
+    /// mismatches. Managed workspace is created or updated to match.
+    fn check_workspace(&self) -> WorkerResult<()> {
+        let perforce_config = self.config.perforce();
+        let workspace = perforce_config.workspace();
+        let client = perforce_config.client();
+
+        let view = match client_view(client, perforce_config.work_dir(), self.config.mappings()) {
+            Ok(view) => view,
+            Err(err) if workspace.is_none() => {
+                warn!("Workspace {} check skipped: {}", client, err);
+
+                return Ok(());
+            }
+            Err(err) => return Err(WorkerError::perforce_error(err)),
+        };
+
+        let mut p4_client = self.perforce_client();
+
+        p4_client.login().map_err(WorkerError::perforce_error)?;
+
+        let actual = p4_client
+            .client_spec()
+            .map_err(WorkerError::perforce_error)?;
+        let expected = match workspace {
+            Some(workspace) => ClientSpec::new(
+                perforce_config.work_dir(),
+                &client_options(workspace.options()),
+                workspace.line_end(),
+                view,
+            ),
+            None => ClientSpec::new(
+                perforce_config.work_dir(),
+                actual.options(),
+                actual.line_end(),
+                view,
+            ),
+        };
+        let mismatches = spec_mismatches(&expected, &actual);
+
+        if !actual.exists() {
+            warn!("Workspace {} does not exist", client);
+        }
+
+        for (field, expected, actual) in &mismatches {
+            warn!(
+                "Workspace {} {} mismatch, expected = {}, actual = {}",
+                client, field, expected, actual
+            );
+        }
+
+        if workspace.is_some() && (!actual.exists() || !mismatches.is_empty()) {
+            info!("Updating workspace {}", client);
+            p4_client
+                .set_client_spec(&expected)
+                .map_err(WorkerError::perforce_error)?;
+        }
+
+        p4_client.logout().map_err(WorkerError::perforce_error)?;
+
+        Ok(())
+    }
+
+    /// Renews or takes over lease with new Perforce connection.
+    fn acquire_lease(&self, lease: &LeaseConfig) -> WorkerResult<bool> {
+        let mut p4_client = self.perforce_client();
+
+        p4_client.login().map_err(WorkerError::perforce_error)?;
+
+        let is_leader = self.renew_lease(lease, &mut p4_client)?;
+
+        p4_client.logout().map_err(WorkerError::perforce_error)?;
+
+        Ok(is_leader)
+    }
+
+    /// Renews or takes over lease stored in Perforce key. Returns `true` if this process holds
+    /// the lease and should synchronize mappings. Lease is replaced with compare and set, so
+    /// only one of concurrent processes gets it.
+    fn renew_lease(
+        &self,
+        lease: &LeaseConfig,
+        p4_client: &mut PerforceClient,
+    ) -> WorkerResult<bool> {
+        let owner = lock::locker();
+        let now = get_time().sec;
+        let value = p4_client
+            .key(lease.key())
+            .map_err(WorkerError::perforce_error)?;
+
+        if let Some((holder, expires)) = parse_lease(&value) {
+            if holder != owner && expires > now {
+                info!(
+                    "Lease held by {}, expires in {} seconds",
+                    holder,
+                    expires - now
+                );
+
+                return Ok(false);
+            } else if holder != owner {
+                warn!("Taking over expired lease from {}", holder);
+            }
+        }
+
+        let lease_value = format!("{} {}", owner, now + lease.duration() as i64);
+
+        if p4_client
+            .compare_and_set_key(lease.key(), &value, &lease_value)
+            .map_err(WorkerError::perforce_error)?
+        {
+            debug!("Lease renewed, value = {}", lease_value);
+
+            Ok(true)
+        } else {
+            warn!("Lease taken by another process");
+
+            Ok(false)
+        }
+    }
+
+    /// Imports next batch of changes. Returns number of changes left to import.
+    fn process_mapping(&self, mapping: &MappingConfig, batch_size: usize) -> WorkerResult<u64> {
+        info!("Processing mapping, depot_path = {}", mapping.depot_path());
+        let depot_paths = mapping.depot_paths();
         let bookmark = mapping.bookmark();
 
-        let perforce_config = self.config.perforce();
-        let mut p4_client = PerforceClient::new(
-            perforce_config.command(),
-            perforce_config.work_dir(),
-            perforce_config.client(),
-            perforce_config.port(),
-            perforce_config.user(),
-            perforce_config.password(),
-            perforce_config.ignore(),
-        );
+        let _workspace_lock =
+            Lock::acquire(self.workspace_lock_path()).map_err(WorkerError::lock_error)?;
+        let _repository_lock =
+            Lock::acquire(self.repository_lock_path(mapping)).map_err(WorkerError::lock_error)?;
 
-        let mercurial_config = self.config.mercurial();
-        let hg_client = MercurialClient::new(mercurial_config.command(), mapping.local_directory());
+        let mut p4_client = self.perforce_client();
+        let hg_client = self.mercurial_client(mapping);
+        let journal = Journal::new(mapping.local_directory(), bookmark);
 
         p4_client.login().map_err(WorkerError::perforce_error)?;
+        self.recover(mapping, &journal, &mut p4_client, &hg_client)?;
         hg_client
             .update(bookmark)
             .map_err(WorkerError::mercurial_error)?;
 
-        let commit = match hg_client
+        let mut previous = hg_client
             .last_commit(bookmark)
-            .map_err(WorkerError::mercurial_error)?
-        {
+            .map_err(WorkerError::mercurial_error)?;
+        let mut processed = last_processed(previous, &journal)?;
+
+        if let (None, Some(start)) = (processed, mapping.start_change()) {
+            if self.import_start(mapping, start, &mut p4_client, &hg_client)? {
+                previous = Some(start);
+            }
+
+            processed = Some(start);
+        }
+
+        let commit = match processed {
             Some(commit) => commit + 1,
             None => 1,
         };
 
+        self.status.set_last_imported_change(bookmark, previous);
+
         let changes = p4_client
-            .changes(depot_directory, commit)
+            .changes(&depot_paths, commit)
             .map_err(WorkerError::perforce_error)?;
 
+        self.status
+            .set_head_change(bookmark, changes.last().cloned().or(previous));
+
         if changes.is_empty() {
             info!("No more changes");
+            metrics::set_lag(bookmark, 0, 0);
 
-            return Ok(());
+            return Ok(0);
         }
 
         let mut have_changes = false;
+        let batch_length = match mapping.mode() {
+            ImportMode::Changes => changes.len().min(batch_size),
+            ImportMode::Snapshot => changes.len(),
+        };
+        // Snapshot covers all pending changes at once.
+        let groups = match mapping.mode() {
+            ImportMode::Changes => {
+                let mut batch = Vec::with_capacity(batch_length);
 
-        for id in changes.into_iter().take(batch_size) {
-            info!("Processing change {}", id);
-
-            let change = p4_client.change(id).map_err(WorkerError::perforce_error)?;
-            let message = format_change(&change);
+                for &id in &changes[..batch_length] {
+                    batch.push(p4_client.change(id).map_err(WorkerError::perforce_error)?);
+                }
 
-            p4_client
-                .sync(depot_directory, id)
-                .map_err(WorkerError::perforce_error)?;
-            p4_client
-                .clean(depot_directory)
-                .map_err(WorkerError::perforce_error)?;
+                group_changes(mapping, batch)
+            }
+            ImportMode::Snapshot => vec![p4_client
+                .describe_changes(
+                    &mapping.depot_paths(),
+                    changes[0],
+                    changes[batch_length - 1],
+                )
+                .map_err(WorkerError::perforce_error)?],
+        };
 
-            let large_files = hg_client
-                .get_large_files(10 * 1024 * 1024)
-                .map_err(WorkerError::mercurial_error)?;
-            let mut has_changes = !large_files.is_empty();
+        for group in groups {
+            if self.control.should_interrupt(bookmark) {
+                info!("Mapping {} interrupted", bookmark);
 
-            for large_file in large_files {
-                hg_client
-                    .add_large(&large_file)
-                    .map_err(WorkerError::mercurial_error)?;
+                break;
             }
 
-            if !has_changes {
-                let changed_files = hg_client.status().map_err(WorkerError::mercurial_error)?;
+            if let Some(lease) = self.config.lease() {
+                if !self.renew_lease(lease, &mut p4_client)? {
+                    warn!("Lease lost, mapping {} stopped", bookmark);
 
-                has_changes = !changed_files.is_empty();
+                    break;
+                }
             }
 
-            if has_changes {
-                hg_client
-                    .addremove(80)
-                    .map_err(WorkerError::mercurial_error)?;
-                hg_client
-                    .commit(&message, change.date(), change.user())
-                    .map_err(WorkerError::mercurial_error)?;
-
+            if let Some(head) = self.import_group(
+                mapping,
+                group,
+                previous,
+                have_changes,
+                &mut p4_client,
+                &hg_client,
+            )? {
                 have_changes = true;
+                previous = Some(head);
             }
         }
 
+        logging::set_change(None);
+
+        let lag_seconds = match changes.get(batch_length) {
+            Some(&id) => {
+                let change = p4_client.change(id).map_err(WorkerError::perforce_error)?;
+
+                get_time().sec - change.timestamp()
+            }
+            None => 0,
+        };
+
+        let lag_changes = (changes.len() - batch_length) as u64;
+
+        metrics::set_lag(bookmark, lag_changes, lag_seconds);
+
         p4_client.logout().map_err(WorkerError::perforce_error)?;
 
         if have_changes {
+            if let Some(previous) = previous {
+                journal
+                    .write(&JournalEntry::new(
+                        JournalStep::Push,
+                        previous,
+                        Some(previous),
+                        true,
+                    ))
+                    .map_err(WorkerError::journal_error)?;
+            }
+
             hg_client.push().map_err(WorkerError::mercurial_error)?;
+
+            metrics::pushed(bookmark);
+        }
+
+        journal.clear().map_err(WorkerError::journal_error)?;
+
+        Ok(lag_changes)
+    }
+
+    /// Imports state of mapping sources at start change as the first commit of repository, so
+    /// history before it is not imported. Returns `false` if there were no files to commit.
+    fn import_start(
+        &self,
+        mapping: &MappingConfig,
+        start: u32,
+        p4_client: &mut PerforceClient,
+        hg_client: &MercurialClient,
+    ) -> WorkerResult<bool> {
+        let depot_paths = mapping.depot_paths();
+        let bookmark = mapping.bookmark();
+        let journal = Journal::new(mapping.local_directory(), bookmark);
+
+        logging::set_change(Some(start));
+        info!("Importing initial snapshot at change {}", start);
+
+        let change = p4_client
+            .change(start)
+            .map_err(WorkerError::perforce_error)?;
+        let files = p4_client
+            .head_files(&depot_paths, start)
+            .map_err(WorkerError::perforce_error)?;
+
+        let commit = CommitState {
+            first: None,
+            head: start,
+            latest: &change,
+            changes: vec![(&change, &files)],
+            files: &files,
+            message: format_snapshot(&[], &mapping.depot_path(), start),
+            previous: None,
+            pending_push: false,
+        };
+
+        if !self.commit_state(mapping, &journal, commit, p4_client, hg_client)? {
+            info!(
+                "Initial snapshot at change {} has no files to import",
+                start
+            );
+            journal.clear().map_err(WorkerError::journal_error)?;
+            logging::set_change(None);
+
+            return Ok(false);
+        }
+
+        metrics::change_imported(bookmark);
+        self.status.set_last_imported_change(bookmark, Some(start));
+
+        journal
+            .write(&JournalEntry::new(
+                JournalStep::Push,
+                start,
+                Some(start),
+                true,
+            ))
+            .map_err(WorkerError::journal_error)?;
+        hg_client.push().map_err(WorkerError::mercurial_error)?;
+        metrics::pushed(bookmark);
+        journal.clear().map_err(WorkerError::journal_error)?;
+        logging::set_change(None);
+
+        Ok(true)
+    }
+
+    /// Imports changes as a single commit of the last change state: all pending changes in
+    /// snapshot mode, group of consecutive changes or single change with its own message. Changes
+    /// skipped by filter rules are not listed in commit message, but their files are part of the
+    /// snapshot. Snapshot takes all files at head change. Returns last change if commit was made.
+    fn import_group(
+        &self,
+        mapping: &MappingConfig,
+        changes: Vec<Change>,
+        previous: Option<u32>,
+        pending_push: bool,
+        p4_client: &mut PerforceClient,
+        hg_client: &MercurialClient,
+    ) -> WorkerResult<Option<u32>> {
+        let depot_paths = mapping.depot_paths();
+        let journal = Journal::new(mapping.local_directory(), mapping.bookmark());
+        let (first, head) = match (changes.first(), changes.last()) {
+            (Some(first), Some(last)) => (first.change(), last.change()),
+            _ => return Ok(None),
+        };
+
+        let single = changes.len() == 1;
+
+        logging::set_change(Some(head));
+
+        if single {
+            info!("Processing change {}", head);
+        } else {
+            info!(
+                "Processing changes up to {} as one commit, {} changes",
+                head,
+                changes.len()
+            );
+        }
+
+        let mut included = Vec::new();
+        let mut included_files = Vec::new();
+        let mut files = Vec::new();
+
+        for change in changes {
+            let id = change.change();
+            let change_files = match mapping.mode() {
+                ImportMode::Snapshot => Vec::new(),
+                ImportMode::Changes => p4_client.files(id).map_err(WorkerError::perforce_error)?,
+            };
+
+            if is_skipped(mapping, &change) {
+                info!("Change {} skipped by filter rules", id);
+                files.extend(change_files);
+            } else {
+                files.extend(change_files.iter().cloned());
+                included.push(change);
+                included_files.push(change_files);
+            }
+        }
+
+        let latest = match included.last() {
+            Some(latest) => latest,
+            None => {
+                info!("All changes up to {} skipped by filter rules", head);
+                journal
+                    .set_last_skipped(head, previous)
+                    .map_err(WorkerError::journal_error)?;
+
+                return Ok(None);
+            }
+        };
+        // Snapshot changes are listed with UTC dates, commit date is read from the latest one.
+        let (latest, files) = match mapping.mode() {
+            ImportMode::Snapshot => (
+                p4_client
+                    .change(latest.change())
+                    .map_err(WorkerError::perforce_error)?,
+                p4_client
+                    .head_revisions(&depot_paths, head)
+                    .map_err(WorkerError::perforce_error)?,
+            ),
+            ImportMode::Changes => (latest.clone(), latest_files(files)),
+        };
+
+        let audit_changes: Vec<_> = included
+            .iter()
+            .zip(&included_files)
+            .map(|(change, change_files)| (change, change_files.as_slice()))
+            .collect();
+        let commit = CommitState {
+            first: Some(first),
+            head,
+            latest: &latest,
+            changes: audit_changes,
+            files: &files,
+            message: match mapping.mode() {
+                ImportMode::Snapshot => format_snapshot(&included, &mapping.depot_path(), head),
+                ImportMode::Changes if single => format_change(
+                    &latest,
+                    &mapping.depot_path(),
+                    self.config
+                        .mercurial()
+                        .message_template()
+                        .map(String::as_str),
+                ),
+                ImportMode::Changes => format_group(&included, &mapping.depot_path()),
+            },
+            previous,
+            pending_push,
+        };
+
+        if !self.commit_state(mapping, &journal, commit, p4_client, hg_client)? {
+            info!("Changes up to {} have no files to import", head);
+
+            return Ok(None);
+        }
+
+        metrics::change_imported(mapping.bookmark());
+        self.status
+            .set_last_imported_change(mapping.bookmark(), Some(head));
+
+        Ok(Some(head))
+    }
+
+    /// Synchronizes workspace to state of head change and commits it, journal is written before
+    /// every step. Shared by initial snapshot, commits of several changes and backfill. Returns
+    /// `false` and records head change as skipped if there were no files to commit.
+    fn commit_state(
+        &self,
+        mapping: &MappingConfig,
+        journal: &Journal,
+        commit: CommitState,
+        p4_client: &mut PerforceClient,
+        hg_client: &MercurialClient,
+    ) -> WorkerResult<bool> {
+        let depot_paths = mapping.depot_paths();
+        let head = commit.head;
+        let previous = commit.previous;
+        let pending_push = commit.pending_push;
+
+        journal
+            .write(&JournalEntry::new(
+                JournalStep::Sync,
+                head,
+                previous,
+                pending_push,
+            ))
+            .map_err(WorkerError::journal_error)?;
+
+        match commit.first {
+            Some(first) => p4_client.sync_changes(&depot_paths, first, head),
+            None => p4_client.sync(&depot_paths, head),
+        }
+        .map_err(WorkerError::perforce_error)?;
+        p4_client
+            .clean_files(&synced_files(mapping, commit.files))
+            .map_err(WorkerError::perforce_error)?;
+
+        self.remove_excluded(mapping, commit.files, p4_client)?;
+        self.apply_file_types(mapping, commit.files, hg_client)?;
+
+        journal
+            .write(&JournalEntry::new(
+                JournalStep::Stage,
+                head,
+                previous,
+                pending_push,
+            ))
+            .map_err(WorkerError::journal_error)?;
+
+        if !self.stage_files(mapping, commit.files, hg_client)? {
+            journal
+                .set_last_skipped(head, previous)
+                .map_err(WorkerError::journal_error)?;
+
+            return Ok(false);
+        }
+
+        let author = self
+            .authors
+            .resolve(commit.latest.user(), p4_client)
+            .map_err(WorkerError::perforce_error)?;
+        let audit = self.audit_records(mapping, &commit.changes, &author);
+
+        let entry = JournalEntry::new(JournalStep::Commit, head, previous, pending_push)
+            .with_audit(audit.clone());
+
+        journal.write(&entry).map_err(WorkerError::journal_error)?;
+        hg_client
+            .commit(&commit.message, commit.latest.date(), &author)
+            .map_err(WorkerError::mercurial_error)?;
+        self.append_audit(&audit, ".", false, hg_client)?;
+
+        if mapping.verify() {
+            self.verify_commit(mapping, journal, &entry, p4_client, hg_client)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Adds ignored and large files and marks new and removed files. Returns `false` if working
+    /// copy has nothing to commit.
+    fn stage_files(
+        &self,
+        mapping: &MappingConfig,
+        files: &[ChangeFile],
+        hg_client: &MercurialClient,
+    ) -> WorkerResult<bool> {
+        self.back_out(mapping, files, hg_client)?;
+        self.check_ignored(mapping, files, hg_client)?;
+
+        let large_files = hg_client
+            .get_large_files(LARGE_FILE_SIZE)
+            .map_err(WorkerError::mercurial_error)?;
+        let mut has_changes = !large_files.is_empty();
+
+        for large_file in large_files {
+            hg_client
+                .add_large(&large_file)
+                .map_err(WorkerError::mercurial_error)?;
+        }
+
+        if !has_changes {
+            let changed_files = hg_client.status().map_err(WorkerError::mercurial_error)?;
+
+            has_changes = !changed_files.is_empty();
+        }
+
+        if has_changes {
+            hg_client
+                .addremove(80)
+                .map_err(WorkerError::mercurial_error)?;
+        }
+
+        Ok(has_changes)
+    }
+
+    /// Reverts working copy changes of files which are not part of imported changes. Workspace
+    /// may have files of skipped changes at newer revisions, for example after rollback, such
+    /// files stay as they are in Mercurial until imported change modifies them.
+    fn back_out(
+        &self,
+        mapping: &MappingConfig,
+        files: &[ChangeFile],
+        hg_client: &MercurialClient,
+    ) -> WorkerResult<()> {
+        let paths: HashSet<_> = files
+            .iter()
+            .filter_map(|file| local_path(mapping, file.depot_file()))
+            .collect();
+        let unknown: HashSet<_> = hg_client
+            .unknown()
+            .map_err(WorkerError::mercurial_error)?
+            .into_iter()
+            .filter(|path| !paths.contains(path))
+            .collect();
+        let changed: Vec<_> = hg_client
+            .status()
+            .map_err(WorkerError::mercurial_error)?
+            .into_iter()
+            .filter(|path| !paths.contains(path) && !unknown.contains(path))
+            .collect();
+
+        for path in &unknown {
+            warn!(
+                "Removing file {} which is not part of imported changes",
+                path
+            );
+            hg_client
+                .remove_unknown(path)
+                .map_err(WorkerError::mercurial_error)?;
+        }
+
+        if !changed.is_empty() {
+            warn!(
+                "Reverting {} files which are not part of imported changes",
+                changed.len()
+            );
+            hg_client
+                .revert(&changed)
+                .map_err(WorkerError::mercurial_error)?;
         }
 
         Ok(())
     }
-}
 
-fn format_change(change: &Change) -> String {
-    let mut message = format!("change #{}\n", change.change());
+    /// Removes files excluded by mapping filters from workspace, so they are not left as unknown
+    /// files in Mercurial working copy. Removed files are not restored by `p4 clean`.
+    fn remove_excluded(
+        &self,
+        mapping: &MappingConfig,
+        files: &[ChangeFile],
+        p4_client: &mut PerforceClient,
+    ) -> WorkerResult<()> {
+        let filters = match mapping.filters() {
+            Some(filters) => filters,
+            None => return Ok(()),
+        };
+        let excluded: Vec<_> = files
+            .iter()
+            .filter(|file| !file.is_deleted())
+            .filter(|file| {
+                local_path(mapping, file.depot_file())
+                    .is_some_and(|path| !filters.is_included(&path))
+            })
+            .map(|file| file.depot_file().to_string())
+            .collect();
+
+        if excluded.is_empty() {
+            return Ok(());
+        }
+
+        info!("Removing {} files excluded by filter rules", excluded.len());
+        p4_client
+            .unsync_files(&excluded)
+            .map_err(WorkerError::perforce_error)
+    }
+
+    /// Verifies working copy of just made commit, differences are reported as failure. Failure
+    /// is recorded in journal, so commit is not pushed until operator acts.
+    fn verify_commit(
+        &self,
+        mapping: &MappingConfig,
+        journal: &Journal,
+        entry: &JournalEntry,
+        p4_client: &mut PerforceClient,
+        hg_client: &MercurialClient,
+    ) -> WorkerResult<()> {
+        let change = entry.change();
+        let tracked = hg_client
+            .manifest(".")
+            .map_err(WorkerError::mercurial_error)?;
+        let report = self.verify_change(
+            mapping,
+            change,
+            Path::new(mapping.local_directory()),
+            &tracked,
+            p4_client,
+        )?;
+
+        log_verification(change, &report);
 
-    for ch in change.description().chars() {
-        if ch.is_ascii() {
-            message.push(ch);
+        if report.is_clean() {
+            Ok(())
         } else {
-            let escaped = format!("{}", ch.escape_unicode());
+            journal
+                .write(&entry.clone().with_step(JournalStep::VerifyFailed))
+                .map_err(WorkerError::journal_error)?;
+
+            Err(WorkerError::verification_failed(change))
+        }
+    }
+
+    /// Creates audit records of changes imported as one commit, no records are created if audit
+    /// log is not configured.
+    fn audit_records(
+        &self,
+        mapping: &MappingConfig,
+        changes: &[(&Change, &[ChangeFile])],
+        author: &str,
+    ) -> Vec<AuditRecord> {
+        if self.config.audit_log().is_none() {
+            return Vec::new();
+        }
+
+        let depot_paths = mapping.depot_paths();
+        let imported_at = now_utc().rfc3339().to_string();
+
+        changes
+            .iter()
+            .map(|&(change, files)| {
+                let files = files
+                    .iter()
+                    .filter(|file| {
+                        depot_paths
+                            .iter()
+                            .any(|path| file.depot_file().starts_with(path.as_str()))
+                    })
+                    .count();
+                let submitted_at = change.date().rfc3339().to_string();
+
+                AuditRecord::new(
+                    mapping.bookmark(),
+                    change.change(),
+                    author,
+                    files,
+                    &submitted_at,
+                    &imported_at,
+                )
+            })
+            .collect()
+    }
+
+    /// Appends audit records of commit at revision. On recovery records which were already
+    /// appended before interruption are skipped.
+    fn append_audit(
+        &self,
+        records: &[AuditRecord],
+        revision: &str,
+        recovering: bool,
+        hg_client: &MercurialClient,
+    ) -> WorkerResult<()> {
+        let audit_log = match self.config.audit_log() {
+            Some(path) => AuditLog::new(path),
+            None => return Ok(()),
+        };
+
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        let node = hg_client
+            .node(revision)
+            .map_err(WorkerError::mercurial_error)?;
+
+        for record in records {
+            if recovering
+                && audit_log
+                    .contains(record.bookmark(), record.change())
+                    .map_err(WorkerError::audit_error)?
+            {
+                continue;
+            }
+
+            let mut record = record.clone();
+
+            record.set_node(&node);
+            audit_log
+                .append(&record)
+                .map_err(WorkerError::audit_error)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets executable bit and symlinks of synchronized files according to Perforce filetypes.
+    /// Every mismatch between workspace and filetype is reported. Keywords of `+k` files are
+    /// collapsed and content transforms are applied if mapping requires it.
+    fn apply_file_types(
+        &self,
+        mapping: &MappingConfig,
+        files: &[ChangeFile],
+        hg_client: &MercurialClient,
+    ) -> WorkerResult<()> {
+        for file in files.iter().filter(|file| !file.is_deleted()) {
+            let path = match local_path(mapping, file.depot_file()) {
+                Some(path) => path,
+                None => continue,
+            };
+            let file_type = file.file_type();
+
+            if hg_client
+                .ensure_flags(&path, file_type.is_executable(), file_type.is_symlink())
+                .map_err(WorkerError::mercurial_error)?
+            {
+                warn!(
+                    "File {} did not match Perforce filetype {}, fixed",
+                    path, file_type
+                );
+            }
+
+            let mut full_path = PathBuf::from(mapping.local_directory());
+            full_path.push(&path);
+
+            if mapping.collapse_keywords()
+                && file_type.has_modifier('k')
+                && transform::collapse_keywords(&full_path, file_type.has_modifier('o'))
+                    .map_err(WorkerError::transform_error)?
+            {
+                debug!("Keywords collapsed in {}", path);
+            }
 
-            message.push_str(&escaped);
+            if let Some(rule) = mapping.transform(file_type.base(), &path) {
+                let utf16 = file_type.base() == "utf16";
+                let utf8 = rule.utf8() && utf16;
+                let lf = rule.line_endings() == LineEndings::Lf
+                    && (utf8 || is_text_type(file_type.base()));
+
+                if transform::convert(&full_path, utf8, lf).map_err(WorkerError::transform_error)? {
+                    debug!("Content of {} converted", path);
+                }
+            }
         }
+
+        Ok(())
     }
 
-    message
+    /// Finds files of change ignored by `.hgignore`, which are skipped by `hg addremove`. Ignored
+    /// files are reported or added according to mapping policy.
+    fn check_ignored(
+        &self,
+        mapping: &MappingConfig,
+        files: &[ChangeFile],
+        hg_client: &MercurialClient,
+    ) -> WorkerResult<()> {
+        let paths: Vec<_> = files
+            .iter()
+            .filter(|file| !file.is_deleted())
+            .filter_map(|file| local_path(mapping, file.depot_file()))
+            .collect();
+
+        if paths.is_empty() {
+            return Ok(());
+        }
+
+        let ignored = hg_client
+            .ignored(&paths)
+            .map_err(WorkerError::mercurial_error)?;
+
+        for path in ignored {
+            match mapping.ignored_files() {
+                IgnoredFiles::Report => warn!("File {} is ignored by Mercurial", path),
+                IgnoredFiles::Add => {
+                    info!("Adding ignored file {}", path);
+
+                    let mut full_path = PathBuf::from(mapping.local_directory());
+                    full_path.push(&path);
+
+                    let is_large = full_path
+                        .symlink_metadata()
+                        .map(|metadata| metadata.len() >= LARGE_FILE_SIZE)
+                        .unwrap_or(false);
+
+                    if is_large {
+                        hg_client.add_large(&path)
+                    } else {
+                        hg_client.add(&path)
+                    }
+                    .map_err(WorkerError::mercurial_error)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finishes or rolls back change left by interrupted run. Changes which were not committed
+    /// are rolled back: Perforce workspace synchronized back to last imported change and
+    /// Mercurial working copy cleaned. Committed changes are verified if mapping requires it and
+    /// pushed. Commit which failed verification blocks mapping until operator removes it from
+    /// bookmark or pushes it and clears journal.
+    fn recover(
+        &self,
+        mapping: &MappingConfig,
+        journal: &Journal,
+        p4_client: &mut PerforceClient,
+        hg_client: &MercurialClient,
+    ) -> WorkerResult<()> {
+        let entry = match journal.read().map_err(WorkerError::journal_error)? {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+
+        warn!(
+            "Recovering interrupted change {}, step = {:?}",
+            entry.change(),
+            entry.step()
+        );
+        let bookmark = mapping.bookmark();
+        let mut need_push = entry.pending_push();
+
+        match entry.step() {
+            JournalStep::Sync | JournalStep::Stage | JournalStep::Backfill => {
+                self.rollback(mapping, entry.previous(), p4_client, hg_client)?;
+            }
+            JournalStep::Commit => {
+                let last_commit = hg_client
+                    .last_commit(bookmark)
+                    .map_err(WorkerError::mercurial_error)?;
+
+                if last_commit == Some(entry.change()) {
+                    info!("Change {} was committed", entry.change());
+                    self.append_audit(entry.audit(), bookmark, true, hg_client)?;
+
+                    if mapping.verify() {
+                        self.verify_commit(mapping, journal, &entry, p4_client, hg_client)?;
+                    }
+
+                    need_push = true;
+                } else {
+                    self.rollback(mapping, entry.previous(), p4_client, hg_client)?;
+                }
+            }
+            JournalStep::VerifyFailed => {
+                let last_commit = hg_client
+                    .last_commit(bookmark)
+                    .map_err(WorkerError::mercurial_error)?;
+
+                if last_commit == Some(entry.change()) {
+                    warn!(
+                        "Commit of change {} failed verification, mapping {} is blocked",
+                        entry.change(),
+                        bookmark
+                    );
+
+                    return Err(WorkerError::verification_failed(entry.change()));
+                }
+
+                info!("Commit of change {} was removed", entry.change());
+                self.rollback(mapping, entry.previous(), p4_client, hg_client)?;
+            }
+            JournalStep::Push => {
+                need_push = true;
+            }
+        }
+
+        if need_push {
+            hg_client.push().map_err(WorkerError::mercurial_error)?;
+
+            metrics::pushed(bookmark);
+        }
+
+        journal.clear().map_err(WorkerError::journal_error)?;
+
+        info!("Recovery complete");
+
+        Ok(())
+    }
+
+    /// Recovers interrupted backfill. The first commit of history has no bookmark until it is
+    /// made, so root commit of interrupted change is bookmarked. Made commit is audited and
+    /// verified as in mapping recovery, commit which failed verification blocks backfill. Other
+    /// steps are repeated by backfill, as it starts from clean working copy of history bookmark.
+    fn recover_history(
+        &self,
+        mapping: &MappingConfig,
+        history: &str,
+        journal: &Journal,
+        p4_client: &mut PerforceClient,
+        hg_client: &MercurialClient,
+    ) -> WorkerResult<()> {
+        let entry = match journal.read().map_err(WorkerError::journal_error)? {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+
+        warn!(
+            "Recovering interrupted backfill of change {}, step = {:?}",
+            entry.change(),
+            entry.step()
+        );
+
+        let committed =
+            entry.step() == JournalStep::Commit || entry.step() == JournalStep::VerifyFailed;
+        let mut has_history = hg_client
+            .bookmarks()
+            .map_err(WorkerError::mercurial_error)?
+            .iter()
+            .any(|bookmark| bookmark == history);
+
+        if committed && entry.previous().is_none() && !has_history {
+            let root = hg_client
+                .changes("roots(all())")
+                .map_err(WorkerError::mercurial_error)?
+                .into_iter()
+                .find(|&(_, change)| change == entry.change());
+
+            if let Some((node, _)) = root {
+                hg_client
+                    .set_bookmark(history, &node)
+                    .map_err(WorkerError::mercurial_error)?;
+                has_history = true;
+            }
+        }
+
+        let last_commit = if has_history {
+            hg_client
+                .last_commit(history)
+                .map_err(WorkerError::mercurial_error)?
+        } else {
+            None
+        };
+
+        if last_commit == Some(entry.change()) {
+            match entry.step() {
+                JournalStep::Commit => {
+                    info!("Change {} was committed", entry.change());
+                    self.append_audit(entry.audit(), history, true, hg_client)?;
+
+                    if mapping.verify() {
+                        hg_client
+                            .update_clean(history)
+                            .map_err(WorkerError::mercurial_error)?;
+                        self.verify_commit(mapping, journal, &entry, p4_client, hg_client)?;
+                    }
+                }
+                JournalStep::VerifyFailed => {
+                    warn!(
+                        "Commit of change {} failed verification, backfill of {} is blocked",
+                        entry.change(),
+                        history
+                    );
+
+                    return Err(WorkerError::verification_failed(entry.change()));
+                }
+                _ => {}
+            }
+        }
+
+        journal.clear().map_err(WorkerError::journal_error)?;
+
+        info!("Recovery complete");
+
+        Ok(())
+    }
+
+    fn rollback(
+        &self,
+        mapping: &MappingConfig,
+        previous: Option<u32>,
+        p4_client: &mut PerforceClient,
+        hg_client: &MercurialClient,
+    ) -> WorkerResult<()> {
+        let depot_paths = mapping.depot_paths();
+
+        info!("Rolling back to change {:?}", previous);
+
+        match previous {
+            Some(previous) => p4_client.sync(&depot_paths, previous),
+            None => p4_client.unsync(&depot_paths),
+        }
+        .map_err(WorkerError::perforce_error)?;
+        p4_client
+            .clean(&depot_paths)
+            .map_err(WorkerError::perforce_error)?;
+
+        if let (Some(previous), Some(_)) = (previous, mapping.filters()) {
+            let files = p4_client
+                .head_files(&depot_paths, previous)
+                .map_err(WorkerError::perforce_error)?;
+
+            self.remove_excluded(mapping, &files, p4_client)?;
+        }
+
+        hg_client
+            .update_clean(mapping.bookmark())
+            .map_err(WorkerError::mercurial_error)?;
+
+        Ok(())
+    }
+
+    /// Compares files of Mercurial revision placed in `root` with Perforce files at change.
+    /// Content is compared only for files which are not changed on import.
+    fn verify_change(
+        &self,
+        mapping: &MappingConfig,
+        change: u32,
+        root: &Path,
+        tracked: &[String],
+        p4_client: &mut PerforceClient,
+    ) -> WorkerResult<VerifyReport> {
+        let digests = p4_client
+            .digests(&mapping.depot_paths(), change)
+            .map_err(WorkerError::perforce_error)?;
+        let mut skipped = HashSet::new();
+
+        // Files last changed by skipped changes keep previous content in Mercurial until
+        // imported change modifies them, they are not checked.
+        if mapping
+            .filters()
+            .is_some_and(|filters| filters.skips_changes())
+        {
+            let changes: HashSet<_> = digests.iter().map(|file| file.change()).collect();
+
+            // Descriptions of all head changes are read at once, one command per distinct change
+            // is too slow for large depots.
+            if let (Some(&first), Some(&last)) = (changes.iter().min(), changes.iter().max()) {
+                skipped = p4_client
+                    .describe_changes(&mapping.depot_paths(), first, last)
+                    .map_err(WorkerError::perforce_error)?
+                    .iter()
+                    .filter(|change| changes.contains(&change.change()))
+                    .filter(|change| is_skipped(mapping, change))
+                    .map(|change| change.change())
+                    .collect();
+            }
+        }
+
+        let mut unchecked = HashSet::new();
+        let expected: Vec<_> = digests
+            .iter()
+            .filter_map(|file| {
+                let path = local_path(mapping, file.depot_file())?;
+
+                if !mapping
+                    .filters()
+                    .is_none_or(|filters| filters.is_included(&path))
+                {
+                    return None;
+                }
+
+                if skipped.contains(&file.change()) {
+                    unchecked.insert(path);
+
+                    return None;
+                }
+
+                if file.is_deleted() {
+                    return None;
+                }
+
+                let file_type = file.file_type();
+                let digest = if file_type.has_modifier('k')
+                    || file_type.is_symlink()
+                    || file_type.base() == "utf16"
+                    || mapping.transform(file_type.base(), &path).is_some()
+                {
+                    None
+                } else {
+                    Some((file.digest().to_string(), file.size()))
+                };
+
+                Some(ExpectedFile::new(path, digest))
+            })
+            .collect();
+        let tracked: Vec<_> = tracked
+            .iter()
+            .filter(|path| !unchecked.contains(path.strip_prefix(".hglf/").unwrap_or(path)))
+            .cloned()
+            .collect();
+
+        Ok(verify::compare(root, &expected, &tracked))
+    }
+
+    /// Makes initial snapshot commit of mapping with given bookmark at start change. Incremental
+    /// import continues after it. Returns `false` on failure.
+    pub fn import_from(&self, bookmark: &str, start: u32) -> bool {
+        match self.import_from_mapping(bookmark, start) {
+            Ok(()) => true,
+            Err(err) => {
+                error!("{}", err);
+
+                false
+            }
+        }
+    }
+
+    fn import_from_mapping(&self, bookmark: &str, start: u32) -> WorkerResult<()> {
+        let mapping = self
+            .config
+            .mappings()
+            .iter()
+            .find(|mapping| mapping.bookmark() == bookmark)
+            .ok_or_else(|| WorkerError::unknown_mapping(bookmark))?;
+
+        let _workspace_lock =
+            Lock::acquire(self.workspace_lock_path()).map_err(WorkerError::lock_error)?;
+        let _repository_lock =
+            Lock::acquire(self.repository_lock_path(mapping)).map_err(WorkerError::lock_error)?;
+
+        let mut p4_client = self.perforce_client();
+        let hg_client = self.mercurial_client(mapping);
+        let journal = Journal::new(mapping.local_directory(), bookmark);
+
+        p4_client.login().map_err(WorkerError::perforce_error)?;
+        self.recover(mapping, &journal, &mut p4_client, &hg_client)?;
+        hg_client
+            .update(bookmark)
+            .map_err(WorkerError::mercurial_error)?;
+
+        let previous = hg_client
+            .last_commit(bookmark)
+            .map_err(WorkerError::mercurial_error)?;
+
+        if last_processed(previous, &journal)?.is_some() {
+            return Err(WorkerError::already_imported(bookmark));
+        }
+
+        self.import_start(mapping, start, &mut p4_client, &hg_client)?;
+
+        p4_client.logout().map_err(WorkerError::perforce_error)
+    }
+
+    /// Imports changes preceding the first imported change of mapping with given bookmark into
+    /// separate history chain with `BOOKMARK-history` bookmark. Complete chain is linked to
+    /// mapping history with `BOOKMARK-backfill` tag. Returns `false` on failure.
+    pub fn backfill(&self, bookmark: &str) -> bool {
+        match self.backfill_mapping(bookmark) {
+            Ok(()) => true,
+            Err(err) => {
+                error!("{}", err);
+
+                false
+            }
+        }
+    }
+
+    fn backfill_mapping(&self, bookmark: &str) -> WorkerResult<()> {
+        let mapping = self
+            .config
+            .mappings()
+            .iter()
+            .find(|mapping| mapping.bookmark() == bookmark)
+            .ok_or_else(|| WorkerError::unknown_mapping(bookmark))?;
+        let history = format!("{}-history", bookmark);
+
+        let _workspace_lock =
+            Lock::acquire(self.workspace_lock_path()).map_err(WorkerError::lock_error)?;
+        let _repository_lock =
+            Lock::acquire(self.repository_lock_path(mapping)).map_err(WorkerError::lock_error)?;
+
+        let mut p4_client = self.perforce_client();
+        let hg_client = self.mercurial_client(mapping);
+        let journal = Journal::new(mapping.local_directory(), bookmark);
+        let history_journal = Journal::new(mapping.local_directory(), &history);
+
+        p4_client.login().map_err(WorkerError::perforce_error)?;
+        self.recover(mapping, &journal, &mut p4_client, &hg_client)?;
+
+        let head = hg_client
+            .last_commit(bookmark)
+            .map_err(WorkerError::mercurial_error)?;
+        let start = match hg_client
+            .changes(bookmark)
+            .map_err(WorkerError::mercurial_error)?
+            .first()
+        {
+            Some(&(_, start)) => start,
+            None => return Err(WorkerError::not_imported(bookmark)),
+        };
+
+        // Workspace and working copy leave mapping state, interrupted backfill is rolled back by
+        // recovery of mapping.
+        journal
+            .write(&JournalEntry::new(
+                JournalStep::Backfill,
+                start,
+                head,
+                false,
+            ))
+            .map_err(WorkerError::journal_error)?;
+
+        let result = self
+            .recover_history(
+                mapping,
+                &history,
+                &history_journal,
+                &mut p4_client,
+                &hg_client,
+            )
+            .and_then(|_| {
+                self.import_history(mapping, &history, start, head, &mut p4_client, &hg_client)
+            });
+
+        logging::set_change(None);
+
+        // Working copy is returned to mapping bookmark even if backfill failed, so
+        // synchronization does not continue on history chain.
+        let rollback = self.rollback(mapping, head, &mut p4_client, &hg_client);
+
+        if rollback.is_ok() {
+            journal.clear().map_err(WorkerError::journal_error)?;
+        }
+
+        result?;
+        rollback?;
+
+        p4_client.logout().map_err(WorkerError::perforce_error)
+    }
+
+    /// Imports changes before start change into history chain and links complete chain to
+    /// mapping history. Working copy is left on history chain or mapping bookmark.
+    fn import_history(
+        &self,
+        mapping: &MappingConfig,
+        history: &str,
+        start: u32,
+        head: Option<u32>,
+        p4_client: &mut PerforceClient,
+        hg_client: &MercurialClient,
+    ) -> WorkerResult<()> {
+        let depot_paths = mapping.depot_paths();
+        let bookmark = mapping.bookmark();
+        let tag = format!("{}-backfill", bookmark);
+        let history_journal = Journal::new(mapping.local_directory(), history);
+        let has_history = hg_client
+            .bookmarks()
+            .map_err(WorkerError::mercurial_error)?
+            .iter()
+            .any(|name| name == history);
+
+        let mut previous = if has_history {
+            hg_client
+                .last_commit(history)
+                .map_err(WorkerError::mercurial_error)?
+        } else {
+            None
+        };
+        let commit = match last_processed(previous, &history_journal)? {
+            Some(commit) => commit + 1,
+            None => 1,
+        };
+
+        hg_client
+            .update_clean(if has_history { history } else { "null" })
+            .map_err(WorkerError::mercurial_error)?;
+
+        let changes = p4_client
+            .changes(&depot_paths, commit)
+            .map_err(WorkerError::perforce_error)?;
+
+        info!(
+            "Backfilling changes before {} into {}, {} changes",
+            start,
+            history,
+            changes.iter().filter(|&&id| id < start).count()
+        );
+
+        let mut interrupted = false;
+
+        for id in changes.into_iter().filter(|&id| id < start) {
+            if self.control.should_interrupt(bookmark) {
+                info!("Backfill of {} interrupted", bookmark);
+                interrupted = true;
+
+                break;
+            }
+
+            logging::set_change(Some(id));
+            info!("Backfilling change {}", id);
+
+            let change = p4_client.change(id).map_err(WorkerError::perforce_error)?;
+
+            if is_skipped(mapping, &change) {
+                info!("Change {} skipped by filter rules", id);
+                history_journal
+                    .set_last_skipped(id, previous)
+                    .map_err(WorkerError::journal_error)?;
+
+                continue;
+            }
+
+            // The first commit of history holds all files existing at the change.
+            let files = match previous {
+                Some(_) => p4_client.files(id),
+                None => p4_client.head_files(&depot_paths, id),
+            }
+            .map_err(WorkerError::perforce_error)?;
+            let commit = CommitState {
+                first: None,
+                head: id,
+                latest: &change,
+                changes: vec![(&change, &files)],
+                files: &files,
+                message: format_change(
+                    &change,
+                    &mapping.depot_path(),
+                    self.config
+                        .mercurial()
+                        .message_template()
+                        .map(String::as_str),
+                ),
+                previous,
+                pending_push: false,
+            };
+
+            if !self.commit_state(mapping, &history_journal, commit, p4_client, hg_client)? {
+                info!("Change {} has no files to import", id);
+
+                continue;
+            }
+
+            // Active bookmark is moved by following commits.
+            if previous.is_none() {
+                hg_client
+                    .bookmark(history)
+                    .map_err(WorkerError::mercurial_error)?;
+            }
+
+            previous = Some(id);
+        }
+
+        history_journal
+            .clear()
+            .map_err(WorkerError::journal_error)?;
+
+        if previous.is_some() {
+            hg_client
+                .push_bookmark(history)
+                .map_err(WorkerError::mercurial_error)?;
+
+            let history_node = hg_client
+                .node(history)
+                .map_err(WorkerError::mercurial_error)?;
+            let tag_node = hg_client.node(&tag).ok();
+
+            // History is linked only when all changes before start are imported.
+            if !interrupted && tag_node.as_ref() != Some(&history_node) {
+                info!("Linking {} to {} with tag {}", history, bookmark, tag);
+
+                self.rollback(mapping, head, p4_client, hg_client)?;
+
+                let author = self
+                    .authors
+                    .resolve(self.config.perforce().user(), p4_client)
+                    .map_err(WorkerError::perforce_error)?;
+                let message = format_link(&mapping.depot_path(), start, head.unwrap_or(start));
+
+                hg_client
+                    .tag(&tag, &history_node, &message, &author)
+                    .map_err(WorkerError::mercurial_error)?;
+                hg_client.push().map_err(WorkerError::mercurial_error)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Verifies imported changes in range against Perforce digests, last commits of every bookmark
+    /// are verified without first change. Every revision is archived into temporary directory.
+    /// Returns `false` if any difference found.
+    pub fn verify(&self, first: Option<u32>, last: Option<u32>) -> bool {
+        let mut clean = true;
+
+        for mapping in self.config.mappings() {
+            match self.verify_mapping(mapping, first, last) {
+                Ok(mapping_clean) => clean &= mapping_clean,
+                Err(err) => {
+                    error!("{}", err);
+
+                    clean = false;
+                }
+            }
+        }
+
+        clean
+    }
+
+    fn verify_mapping(
+        &self,
+        mapping: &MappingConfig,
+        first: Option<u32>,
+        last: Option<u32>,
+    ) -> WorkerResult<bool> {
+        let bookmark = mapping.bookmark();
+        let hg_client = self.mercurial_client(mapping);
+        let mut p4_client = self.perforce_client();
+        let mut clean = true;
+
+        let mut changes = hg_client
+            .changes(bookmark)
+            .map_err(WorkerError::mercurial_error)?;
+        changes.retain(|&(_, change)| last.is_none_or(|last| change <= last));
+
+        match first {
+            Some(first) => changes.retain(|&(_, change)| change >= first),
+            None => {
+                let skipped = changes.len().saturating_sub(VERIFY_COMMITS);
+                changes.drain(..skipped);
+            }
+        }
+
+        let mut directory = env::temp_dir();
+        directory.push(format!("perforce-sync-verify-{}", process::id()));
+
+        p4_client.login().map_err(WorkerError::perforce_error)?;
+
+        println!("Mapping {} -> {}", mapping.depot_path(), bookmark);
+
+        for (node, change) in changes {
+            let mut root = directory.clone();
+            root.push(&node);
+
+            hg_client
+                .archive(&node, &root)
+                .map_err(WorkerError::mercurial_error)?;
+
+            let tracked = hg_client
+                .manifest(&node)
+                .map_err(WorkerError::mercurial_error)?;
+            let report = self.verify_change(mapping, change, &root, &tracked, &mut p4_client);
+
+            if let Err(err) = fs::remove_dir_all(&root) {
+                warn!("Removing {} failed: {}", root.display(), err);
+            }
+
+            let report = report?;
+
+            if report.is_clean() {
+                println!("  change {}: ok", change);
+            } else {
+                println!("  change {}: differs", change);
+
+                for path in report.missing() {
+                    println!("    missing {}", path);
+                }
+
+                for path in report.extra() {
+                    println!("    extra {}", path);
+                }
+
+                for path in report.differing() {
+                    println!("    differs {}", path);
+                }
+
+                clean = false;
+            }
+        }
+
+        fs::remove_dir(&directory).ok();
+        p4_client.logout().map_err(WorkerError::perforce_error)?;
+
+        Ok(clean)
+    }
+
+    pub fn dry_run(&self) {
+        let batch_size = self.config.batch_size();
+
+        for mapping in self.config.mappings() {
+            if let Err(err) = self.dry_run_mapping(mapping, batch_size) {
+                error!("{}", err);
+            }
+        }
+    }
+
+    fn dry_run_mapping(&self, mapping: &MappingConfig, batch_size: usize) -> WorkerResult<()> {
+        info!("Dry run mapping, depot_path = {}", mapping.depot_path());
+        let depot_paths = mapping.depot_paths();
+        let bookmark = mapping.bookmark();
+
+        let mut p4_client = self.perforce_client();
+        let hg_client = self.mercurial_client(mapping);
+        let journal = Journal::new(mapping.local_directory(), bookmark);
+
+        p4_client.login().map_err(WorkerError::perforce_error)?;
+
+        let previous = hg_client
+            .last_commit(bookmark)
+            .map_err(WorkerError::mercurial_error)?;
+        let mut processed = last_processed(previous, &journal)?;
+
+        println!("Mapping {} -> {}", mapping.depot_path(), bookmark);
+
+        if let (None, Some(start)) = (processed, mapping.start_change()) {
+            println!("  initial snapshot at change {}", start);
+
+            processed = Some(start);
+        }
+
+        let commit = match processed {
+            Some(commit) => commit + 1,
+            None => 1,
+        };
+
+        let changes = p4_client
+            .changes(&depot_paths, commit)
+            .map_err(WorkerError::perforce_error)?;
+
+        println!("  pending changes: {}", changes.len());
+
+        if mapping.mode() == ImportMode::Snapshot && !changes.is_empty() {
+            let head = changes[changes.len() - 1];
+            let mut included = p4_client
+                .describe_changes(&depot_paths, changes[0], head)
+                .map_err(WorkerError::perforce_error)?;
+            included.retain(|change| !is_skipped(mapping, change));
+
+            println!();
+            println!("  snapshot at change {}", head);
+
+            for line in format_snapshot(&included, &mapping.depot_path(), head).lines() {
+                println!("    | {}", line);
+            }
+
+            println!();
+
+            return p4_client.logout().map_err(WorkerError::perforce_error);
+        }
+
+        let mut batch = Vec::new();
+
+        for id in changes.into_iter().take(batch_size) {
+            batch.push(p4_client.change(id).map_err(WorkerError::perforce_error)?);
+        }
+
+        for group in group_changes(mapping, batch) {
+            let latest = &group[group.len() - 1];
+
+            if is_skipped(mapping, latest) {
+                println!();
+                println!("  change {} by {} skipped", latest.change(), latest.user());
+
+                continue;
+            }
+
+            let mut files = Vec::new();
+
+            for change in &group {
+                files.extend(
+                    p4_client
+                        .files(change.change())
+                        .map_err(WorkerError::perforce_error)?,
+                );
+            }
+
+            let message = match group.len() {
+                1 => format_change(
+                    latest,
+                    &mapping.depot_path(),
+                    self.config
+                        .mercurial()
+                        .message_template()
+                        .map(String::as_str),
+                ),
+                _ => format_group(&group, &mapping.depot_path()),
+            };
+
+            println!();
+            let author = self
+                .authors
+                .resolve(latest.user(), &mut p4_client)
+                .map_err(WorkerError::perforce_error)?;
+
+            if group.len() > 1 {
+                println!(
+                    "  changes {} to {} by {} as one commit",
+                    group[0].change(),
+                    latest.change(),
+                    author
+                );
+            } else {
+                println!("  change {} by {}", latest.change(), author);
+            }
+
+            for line in message.lines() {
+                println!("    | {}", line);
+            }
+
+            for file in latest_files(files) {
+                if depot_paths
+                    .iter()
+                    .any(|path| file.depot_file().starts_with(path.as_str()))
+                {
+                    println!(
+                        "    {} {} ({})",
+                        file.action(),
+                        file.depot_file(),
+                        file.file_type()
+                    );
+                }
+            }
+        }
+
+        println!();
+
+        p4_client.logout().map_err(WorkerError::perforce_error)?;
+
+        Ok(())
+    }
+
+    fn workspace_lock_path(&self) -> PathBuf {
+        let mut path = match self.config.lock_directory() {
+            Some(lock_directory) => PathBuf::from(lock_directory),
+            None => env::temp_dir(),
+        };
+        let name: String = self
+            .config
+            .perforce()
+            .client()
+            .chars()
+            .map(|ch| if ch.is_ascii_alphanumeric() { ch } else { '_' })
+            .collect();
+        path.push(format!("perforce-sync-{}.lock", name));
+
+        path
+    }
+
+    fn repository_lock_path(&self, mapping: &MappingConfig) -> PathBuf {
+        let mut path = PathBuf::new();
+        path.push(mapping.local_directory());
+        path.push(".hg");
+        path.push("perforce-sync.lock");
+
+        path
+    }
+
+    fn perforce_client(&self) -> PerforceClient {
+        let perforce_config = self.config.perforce();
+
+        let mut p4_client = PerforceClient::new(
+            perforce_config.command(),
+            perforce_config.work_dir(),
+            perforce_config.client(),
+            perforce_config.port(),
+            perforce_config.user(),
+            perforce_config.password(),
+            perforce_config.ignore(),
+        );
+        p4_client.set_charset(perforce_config.charset().map(String::as_str));
+
+        p4_client
+    }
+
+    fn mercurial_client(&self, mapping: &MappingConfig) -> MercurialClient {
+        let mercurial_config = self.config.mercurial();
+
+        let mut hg_client =
+            MercurialClient::new(mercurial_config.command(), mapping.local_directory());
+
+        if let Some(filters) = mapping.filters() {
+            hg_client.set_filters(filters.include(), filters.exclude());
+        }
+
+        hg_client
+    }
+}
+
+/// Splits changes into groups of consecutive changes imported as one commit according to mapping
+/// grouping rules. Changes skipped by filter rules are never grouped.
+fn group_changes(mapping: &MappingConfig, changes: Vec<Change>) -> Vec<Vec<Change>> {
+    let mut groups: Vec<Vec<Change>> = Vec::new();
+
+    for change in changes {
+        let joins = groups
+            .last()
+            .and_then(|group| group.last())
+            .is_some_and(|last| joins_group(mapping, last, &change));
+
+        match groups.last_mut() {
+            Some(group) if joins => group.push(change),
+            _ => groups.push(vec![change]),
+        }
+    }
+
+    groups
+}
+
+/// Checks whether change is imported in one commit with preceding change.
+fn joins_group(mapping: &MappingConfig, last: &Change, change: &Change) -> bool {
+    let grouping = match mapping.grouping() {
+        Some(grouping) => grouping,
+        None => return false,
+    };
+    let elapsed = change.timestamp() - last.timestamp();
+
+    !is_skipped(mapping, last)
+        && !is_skipped(mapping, change)
+        && elapsed >= 0
+        && elapsed <= grouping.window()
+        && ((grouping.by_user() && change.user() == last.user())
+            || (grouping.matches_description(last.description())
+                && grouping.matches_description(change.description())))
+}
+
+/// Keeps only the latest entry of every depot file, in order of change.
+fn latest_files(files: Vec<ChangeFile>) -> Vec<ChangeFile> {
+    let mut seen = HashSet::new();
+    let mut result: Vec<_> = files
+        .into_iter()
+        .rev()
+        .filter(|file| seen.insert(file.depot_file().to_string()))
+        .collect();
+
+    result.reverse();
+    result
+}
+
+fn log_verification(change: u32, report: &VerifyReport) {
+    if report.is_clean() {
+        info!("Change {} verified", change);
+
+        return;
+    }
+
+    for path in report.missing() {
+        warn!("Change {} verification: missing {}", change, path);
+    }
+
+    for path in report.extra() {
+        warn!("Change {} verification: extra {}", change, path);
+    }
+
+    for path in report.differing() {
+        warn!("Change {} verification: differs {}", change, path);
+    }
+}
+
+/// Returns path of depot file relative to mapping local directory.
+fn local_path(mapping: &MappingConfig, depot_file: &str) -> Option<String> {
+    let depot_file = depot_file
+        .replace("%40", "@")
+        .replace("%23", "#")
+        .replace("%2A", "*")
+        .replace("%25", "%");
+
+    mapping
+        .sources()
+        .iter()
+        .zip(mapping.depot_paths())
+        .find_map(|(source, depot_path)| {
+            depot_file.strip_prefix(depot_path.as_str()).map(|rest| {
+                match source.target_subdir().trim_matches('/') {
+                    "" => rest.to_string(),
+                    subdir => format!("{}/{}", subdir, rest),
+                }
+            })
+        })
+}
+
+/// Returns differing fields of existing workspace spec as `(field, expected, actual)`.
+fn spec_mismatches(
+    expected: &ClientSpec,
+    actual: &ClientSpec,
+) -> Vec<(&'static str, String, String)> {
+    let mut mismatches = Vec::new();
+
+    if !actual.exists() {
+        return mismatches;
+    }
+
+    if expected.root().trim_end_matches('/') != actual.root().trim_end_matches('/') {
+        mismatches.push(("root", expected.root().into(), actual.root().into()));
+    }
+
+    if expected.view() != actual.view() {
+        mismatches.push(("view", expected.view().join("; "), actual.view().join("; ")));
+    }
+
+    if expected.options() != actual.options() {
+        mismatches.push((
+            "options",
+            expected.options().into(),
+            actual.options().into(),
+        ));
+    }
+
+    if expected.line_end() != actual.line_end() {
+        mismatches.push((
+            "line end",
+            expected.line_end().into(),
+            actual.line_end().into(),
+        ));
+    }
+
+    mismatches
+}
+
+fn parse_lease(value: &str) -> Option<(&str, i64)> {
+    let mut parts = value.splitn(2, ' ');
+
+    match (parts.next(), parts.next().map(str::parse)) {
+        (Some(holder), Some(Ok(expires))) => Some((holder, expires)),
+        _ => None,
+    }
+}
+
+/// Last change processed after `previous` commit, skipped change is taken into account only if it
+/// was skipped after the same commit.
+fn last_processed(previous: Option<u32>, journal: &Journal) -> WorkerResult<Option<u32>> {
+    let skipped = journal
+        .last_skipped(previous)
+        .map_err(WorkerError::journal_error)?;
+
+    Ok(previous.max(skipped))
+}
+
+/// Depot files of mapping which exist in workspace after synchronization of changes. Only these
+/// files are cleaned, so keywords and transforms applied to files of earlier changes are kept.
+fn synced_files(mapping: &MappingConfig, files: &[ChangeFile]) -> Vec<String> {
+    files
+        .iter()
+        .filter(|file| !file.is_deleted() && local_path(mapping, file.depot_file()).is_some())
+        .map(|file| file.depot_file().to_string())
+        .collect()
+}
+
+/// Line endings are normalized only in text base types, binary content is never changed.
+fn is_text_type(base: &str) -> bool {
+    matches!(base, "text" | "unicode" | "utf8")
+}
+
+fn is_skipped(mapping: &MappingConfig, change: &Change) -> bool {
+    mapping
+        .filters()
+        .is_some_and(|filters| filters.skips(change.user(), change.description()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::fs::PermissionsExt;
+
+    use time::at_utc;
+    use time::Timespec;
+
+    use testing::config;
+    use testing::config_text;
+    use testing::temp_path;
+
+    #[test]
+    fn parses_lease() {
+        assert_eq!(
+            parse_lease("host:42 1500000000"),
+            Some(("host:42", 1_500_000_000))
+        );
+        assert_eq!(parse_lease("0"), None);
+        assert_eq!(parse_lease("host:42 soon"), None);
+    }
+
+    fn mapping(grouping: &str) -> Config {
+        config(&format!(
+            "mappings:
+  - depot_directory: //depot/project
+    bookmark: project
+    local_directory: /repo
+    filters:
+      users: [\"^build$\"]
+{}",
+            grouping
+        ))
+    }
+
+    fn change(id: u32, time: i64, user: &str, description: &str) -> Change {
+        Change::new(
+            id,
+            at_utc(Timespec::new(time, 0)),
+            user,
+            "ws",
+            description,
+            Vec::new(),
+        )
+    }
+
+    fn group_ids(config: &Config, changes: Vec<Change>) -> Vec<Vec<u32>> {
+        group_changes(&config.mappings()[0], changes)
+            .iter()
+            .map(|group| group.iter().map(Change::change).collect())
+            .collect()
+    }
+
+    #[test]
+    fn groups_changes_of_user_in_window() {
+        let config = mapping(
+            "    grouping:
+      window: 60
+      by_user: true
+",
+        );
+        let changes = vec![
+            change(1, 1000, "robot", "One"),
+            change(2, 1030, "robot", "Two"),
+            change(3, 1080, "robot", "Three"),
+            change(4, 1200, "robot", "Four"),
+            change(5, 1210, "alice", "Five"),
+            change(6, 1220, "alice", "Six"),
+        ];
+
+        assert_eq!(
+            group_ids(&config, changes),
+            vec![vec![1, 2, 3], vec![4], vec![5, 6]]
+        );
+    }
+
+    #[test]
+    fn groups_changes_by_description() {
+        let config = mapping(
+            "    grouping:
+      descriptions: [\"^\\\\[robot\\\\]\"]
+",
+        );
+        let changes = vec![
+            change(1, 1000, "robot", "[robot] Update version"),
+            change(2, 1010, "alice", "[robot] Update translations"),
+            change(3, 1020, "bob", "Fix parser"),
+            change(4, 1030, "build", "[robot] Nightly"),
+            change(5, 1040, "robot", "[robot] Update changelog"),
+        ];
+
+        assert_eq!(
+            group_ids(&config, changes),
+            vec![vec![1, 2], vec![3], vec![4], vec![5]]
+        );
+    }
+
+    #[test]
+    fn keeps_changes_apart_without_grouping() {
+        let config = mapping("");
+        let changes = vec![
+            change(1, 1000, "robot", "One"),
+            change(2, 1001, "robot", "Two"),
+        ];
+
+        assert_eq!(group_ids(&config, changes), vec![vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn normalizes_only_text_types() {
+        assert!(is_text_type("text"));
+        assert!(is_text_type("unicode"));
+        assert!(!is_text_type("binary"));
+        assert!(!is_text_type("ubinary"));
+        assert!(!is_text_type("utf16"));
+    }
+
+    #[test]
+    fn failed_verification_blocks_mapping() {
+        let directory = temp_path("blocked");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(directory.join(".hg")).unwrap();
+        let command = directory.join("hg");
+        let log = directory.join("hg.log");
+
+        // Mercurial stand-in reports bookmark head at change 12 and records its arguments.
+        fs::write(
+            &command,
+            format!(
+                "#!/bin/sh\necho \"$@\" >> {}\nif [ \"$1\" = log ]; then printf 'change #12\\nFix'; fi\n",
+                log.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&command, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let text = config_text(
+            &command.display().to_string(),
+            &format!(
+                "update_interval: 60
+batch_size: 10
+mappings:
+  - depot_directory: //depot/project
+    bookmark: project
+    local_directory: {}
+    verify: true
+",
+                directory.display()
+            ),
+        );
+        let config = Config::from_reader(text.as_bytes()).unwrap();
+        let mapping = &config.mappings()[0];
+        let worker = Worker::new(
+            &config,
+            Arc::new(SyncStatus::new(config.mappings())),
+            Arc::new(Control::new()),
+        );
+        let journal = Journal::new(mapping.local_directory(), mapping.bookmark());
+
+        journal
+            .write(&JournalEntry::new(
+                JournalStep::VerifyFailed,
+                12,
+                Some(10),
+                true,
+            ))
+            .unwrap();
+
+        for _ in 0..2 {
+            match worker.recover(
+                mapping,
+                &journal,
+                &mut worker.perforce_client(),
+                &worker.mercurial_client(mapping),
+            ) {
+                Err(WorkerError::VerificationFailed { change }) => assert_eq!(change, 12),
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+
+        assert_eq!(
+            journal.read().unwrap().unwrap().step(),
+            JournalStep::VerifyFailed
+        );
+        assert!(!fs::read_to_string(&log).unwrap().contains("push"));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
 }